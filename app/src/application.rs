@@ -2,8 +2,9 @@ use std::sync::Arc;
 
 use winit::{
     application::ApplicationHandler,
-    event::WindowEvent,
+    event::{ElementState, KeyEvent, WindowEvent},
     event_loop::{ActiveEventLoop, EventLoop, EventLoopProxy},
+    keyboard::{KeyCode, PhysicalKey},
     window::{Window, WindowId},
 };
 
@@ -15,10 +16,11 @@ use winit::platform::web::WindowExtWebSys;
 #[cfg(not(target_arch = "wasm32"))]
 use crate::utils::load_icon;
 
-use crate::engine::Engine;
+use crate::{engine::Engine, wgpu::WgpuOptions};
 
 pub struct StateInitializationEvent(Engine);
 
+#[allow(clippy::large_enum_variant)]
 pub enum State {
     Uninitialized,
     Initializing,
@@ -28,13 +30,48 @@ pub enum State {
 pub struct Application {
     application_state: State,
     event_loop_proxy: EventLoopProxy<StateInitializationEvent>,
+    wgpu_options: WgpuOptions,
 }
 
 impl Application {
-    pub fn new(event_loop: &EventLoop<StateInitializationEvent>) -> Application {
+    pub fn new(
+        event_loop: &EventLoop<StateInitializationEvent>,
+        wgpu_options: WgpuOptions,
+    ) -> Application {
         Application {
             application_state: State::Uninitialized,
             event_loop_proxy: event_loop.create_proxy(),
+            wgpu_options,
+        }
+    }
+
+    // The engine is created asynchronously and handed back to the event loop with `StateInitializationEvent`
+    fn initialize_engine(&self, window: Arc<Window>) {
+        let engine_future = Engine::new(window, self.wgpu_options.clone());
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let event_loop_proxy = self.event_loop_proxy.clone();
+            spawn_local(async move {
+                let engine = engine_future.await;
+
+                event_loop_proxy
+                    .send_event(StateInitializationEvent(engine))
+                    .unwrap_or_else(|_| {
+                        panic!("Failed to send initialization event");
+                    });
+            });
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let engine = pollster::block_on(engine_future);
+
+            self.event_loop_proxy
+                .send_event(StateInitializationEvent(engine))
+                .unwrap_or_else(|_| {
+                    panic!("Failed to send initialization event");
+                });
         }
     }
 }
@@ -74,32 +111,7 @@ impl ApplicationHandler<StateInitializationEvent> for Application {
             window.set_window_icon(Some(icon));
         }
 
-        let engine_future = Engine::new(window);
-
-        #[cfg(target_arch = "wasm32")]
-        {
-            let event_loop_proxy = self.event_loop_proxy.clone();
-            spawn_local(async move {
-                let engine = engine_future.await;
-
-                event_loop_proxy
-                    .send_event(StateInitializationEvent(engine))
-                    .unwrap_or_else(|_| {
-                        panic!("Failed to send initialization event");
-                    });
-            });
-        }
-
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let engine = pollster::block_on(engine_future);
-
-            self.event_loop_proxy
-                .send_event(StateInitializationEvent(engine))
-                .unwrap_or_else(|_| {
-                    panic!("Failed to send initialization event");
-                });
-        }
+        self.initialize_engine(window);
     }
 
     fn user_event(&mut self, _event_loop: &ActiveEventLoop, event: StateInitializationEvent) {
@@ -120,18 +132,24 @@ impl ApplicationHandler<StateInitializationEvent> for Application {
             return;
         };
 
-        let egui_event_response = engine
-            .renderer
-            .egui
-            .state
-            .on_window_event(&engine.window, &event);
+        // Nothing can be rendered with a lost device, so the engine starts over with a new one
+        if let Some(message) = engine.renderer.device_lost_message() {
+            log::error!("The GPU device was lost, recreating the engine: {message}");
+            let window = engine.window.clone();
+            // The old surface has to be gone before the window gets a new one
+            self.application_state = State::Initializing;
+            self.initialize_engine(window);
+            return;
+        }
+
+        let egui_event_response = engine.egui.state.on_window_event(&engine.window, &event);
 
         if egui_event_response.repaint {
             engine.window.request_redraw();
         }
 
         engine.input(&event);
-        if engine.camera_controller.is_cursor_locked() {
+        if engine.camera_controller.is_controlling_camera() {
             engine.update();
         }
 
@@ -142,11 +160,180 @@ impl ApplicationHandler<StateInitializationEvent> for Application {
         match event {
             WindowEvent::Resized(new_size) => engine.resize(new_size),
 
-            WindowEvent::RedrawRequested => engine.render().unwrap(),
+            WindowEvent::RedrawRequested => {
+                let Err(error) = engine.render() else {
+                    return;
+                };
+
+                match surface_error_action(&error, engine.window.inner_size()) {
+                    SurfaceErrorAction::Reconfigure => {
+                        let window_size = engine.window.inner_size();
+                        if window_size == engine.window_size {
+                            engine.renderer.reconfigure_surface();
+                        } else {
+                            engine.resize(window_size);
+                        }
+                        engine.window.request_redraw();
+                    }
+                    SurfaceErrorAction::Skip => {
+                        log::warn!(
+                            "Failed to get the next surface texture, skipping the frame: {error}"
+                        );
+                    }
+                    SurfaceErrorAction::Exit => {
+                        log::error!("Failed to get the next surface texture, exiting: {error}");
+                        event_loop.exit();
+                    }
+                }
+            }
 
             WindowEvent::CloseRequested => event_loop.exit(),
 
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key,
+                        state,
+                        repeat,
+                        ..
+                    },
+                ..
+            } => match keyboard_shortcut(physical_key, state, repeat) {
+                Some(KeyboardShortcut::FrameMesh) => engine.frame_mesh(),
+                Some(KeyboardShortcut::ToggleRaytracer) => engine.toggle_raytracer(),
+                #[cfg(not(target_arch = "wasm32"))]
+                Some(KeyboardShortcut::SaveScreenshot) => engine.save_screenshot(),
+                None => {}
+            },
+
+            #[cfg(not(target_arch = "wasm32"))]
+            WindowEvent::DroppedFile(path) => engine.load_mesh(&path),
+
             _ => {}
         };
     }
 }
+
+#[derive(Debug, PartialEq)]
+enum KeyboardShortcut {
+    FrameMesh,
+    ToggleRaytracer,
+    #[cfg(not(target_arch = "wasm32"))]
+    SaveScreenshot,
+}
+
+/// The shortcut a key press triggers, held keys don't trigger them again.
+fn keyboard_shortcut(
+    physical_key: PhysicalKey,
+    state: ElementState,
+    repeat: bool,
+) -> Option<KeyboardShortcut> {
+    if state != ElementState::Pressed || repeat {
+        return None;
+    }
+
+    match physical_key {
+        PhysicalKey::Code(KeyCode::KeyF) => Some(KeyboardShortcut::FrameMesh),
+        PhysicalKey::Code(KeyCode::F2) => Some(KeyboardShortcut::ToggleRaytracer),
+        #[cfg(not(target_arch = "wasm32"))]
+        PhysicalKey::Code(KeyCode::KeyP) => Some(KeyboardShortcut::SaveScreenshot),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum SurfaceErrorAction {
+    /// Configure the surface again and redraw.
+    Reconfigure,
+    /// Drop the frame and wait for the next redraw.
+    Skip,
+    /// Stop the event loop, nothing can be drawn anymore.
+    Exit,
+}
+
+/// Decides how to recover from failing to get the next surface texture.
+fn surface_error_action(
+    error: &wgpu::SurfaceError,
+    window_size: winit::dpi::PhysicalSize<u32>,
+) -> SurfaceErrorAction {
+    match error {
+        // A minimized window has no size to configure the surface with,
+        // it's configured again when it's resized back
+        wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated
+            if window_size.width == 0 || window_size.height == 0 =>
+        {
+            SurfaceErrorAction::Skip
+        }
+        // The surface no longer matches the window
+        wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => SurfaceErrorAction::Reconfigure,
+        wgpu::SurfaceError::Timeout => SurfaceErrorAction::Skip,
+        wgpu::SurfaceError::OutOfMemory => SurfaceErrorAction::Exit,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::dpi::PhysicalSize;
+
+    use super::*;
+
+    #[test]
+    fn shortcut_keys_trigger_their_shortcuts() {
+        let shortcut =
+            |key_code| keyboard_shortcut(PhysicalKey::Code(key_code), ElementState::Pressed, false);
+
+        assert_eq!(shortcut(KeyCode::KeyF), Some(KeyboardShortcut::FrameMesh));
+        assert_eq!(
+            shortcut(KeyCode::F2),
+            Some(KeyboardShortcut::ToggleRaytracer)
+        );
+        assert_eq!(
+            shortcut(KeyCode::KeyP),
+            Some(KeyboardShortcut::SaveScreenshot)
+        );
+        // The camera controller's keys
+        assert_eq!(shortcut(KeyCode::KeyW), None);
+    }
+
+    #[test]
+    fn released_and_repeated_keys_trigger_nothing() {
+        let key = PhysicalKey::Code(KeyCode::F2);
+
+        assert_eq!(keyboard_shortcut(key, ElementState::Released, false), None);
+        assert_eq!(keyboard_shortcut(key, ElementState::Pressed, true), None);
+    }
+
+    #[test]
+    fn stale_surfaces_are_reconfigured() {
+        for error in [wgpu::SurfaceError::Lost, wgpu::SurfaceError::Outdated] {
+            assert_eq!(
+                surface_error_action(&error, PhysicalSize::new(800, 600)),
+                SurfaceErrorAction::Reconfigure
+            );
+        }
+    }
+
+    #[test]
+    fn stale_surfaces_of_minimized_windows_are_skipped() {
+        for size in [PhysicalSize::new(0, 0), PhysicalSize::new(800, 0)] {
+            assert_eq!(
+                surface_error_action(&wgpu::SurfaceError::Outdated, size),
+                SurfaceErrorAction::Skip
+            );
+        }
+    }
+
+    #[test]
+    fn timeouts_skip_and_out_of_memory_exits() {
+        let size = PhysicalSize::new(800, 600);
+
+        assert_eq!(
+            surface_error_action(&wgpu::SurfaceError::Timeout, size),
+            SurfaceErrorAction::Skip
+        );
+        assert_eq!(
+            surface_error_action(&wgpu::SurfaceError::OutOfMemory, size),
+            SurfaceErrorAction::Exit
+        );
+    }
+}
@@ -5,6 +5,13 @@ use winit::{
     keyboard::{KeyCode, PhysicalKey},
 };
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProjectionKind {
+    Perspective { fovy: f32 },
+    // `height` is the height of the view volume in world units
+    Orthographic { height: f32 },
+}
+
 pub struct Camera {
     eye: glam::Vec3,
     // The normalized forward vector of the camera is the direction the camera is looking at.
@@ -12,9 +19,13 @@ pub struct Camera {
     // The normalized up vector of the camera is the direction that is considered up for the camera.
     up: glam::Vec3,
     aspect: f32,
-    fovy: f32,
+    projection: ProjectionKind,
     znear: f32,
     zfar: f32,
+    // The diameter of the lens, a zero aperture is a pinhole camera with everything in focus
+    aperture: f32,
+    // How far in front of the camera the raytracer's focal plane is
+    focus_distance: f32,
     world_to_camera: glam::Mat4,
     camera_to_world: glam::Mat4,
     camera_projection: glam::Mat4,
@@ -33,6 +44,8 @@ impl Camera {
         znear: f32,
         zfar: f32,
     ) -> Self {
+        let projection = ProjectionKind::Perspective { fovy };
+
         // The camera's up vector stays close to the global up
         let up = forward.cross(Camera::GLOBAL_UP.cross(forward)).normalize();
 
@@ -42,16 +55,18 @@ impl Camera {
             camera_projection,
             camera_inverse_projection,
             view_projection,
-        ) = Self::calculate_matrices(eye, forward, up, aspect, fovy, znear, zfar);
+        ) = Self::calculate_matrices(eye, forward, up, aspect, projection, znear, zfar);
 
         Self {
             eye,
             forward,
             up,
             aspect,
-            fovy,
+            projection,
             znear,
             zfar,
+            aperture: 0.0,
+            focus_distance: 4.0,
             world_to_camera,
             camera_to_world,
             camera_projection,
@@ -65,8 +80,14 @@ impl Camera {
         self.update_matrices();
     }
 
+    // Switches the camera to a perspective projection
     pub fn set_fovy(&mut self, fovy: f32) {
-        self.fovy = fovy;
+        self.projection = ProjectionKind::Perspective { fovy };
+        self.update_matrices();
+    }
+
+    pub fn set_projection(&mut self, projection: ProjectionKind) {
+        self.projection = projection;
         self.update_matrices();
     }
 
@@ -80,6 +101,14 @@ impl Camera {
         self.update_matrices();
     }
 
+    pub fn set_aperture(&mut self, aperture: f32) {
+        self.aperture = aperture.max(0.0);
+    }
+
+    pub fn set_focus_distance(&mut self, focus_distance: f32) {
+        self.focus_distance = focus_distance;
+    }
+
     pub fn set_eye(&mut self, eye: glam::Vec3) {
         self.eye = eye;
         self.update_matrices();
@@ -95,6 +124,43 @@ impl Camera {
         self.update_matrices();
     }
 
+    /// Moves the camera back along its forward vector until the box fits in view,
+    /// with `padding` as a fraction of the box's size added around it.
+    /// A zero sized box is turned to face without moving the camera.
+    pub fn frame_bounds(&mut self, min: glam::Vec3, max: glam::Vec3, padding: f32) {
+        let center = (min + max) / 2.0;
+        // Fitting the box's bounding sphere keeps it in view from any direction
+        let radius = (max - min).length() / 2.0;
+
+        if radius <= f32::EPSILON {
+            if let Some(forward) = (center - self.eye).try_normalize() {
+                self.forward = forward;
+                self.up = forward.cross(Camera::GLOBAL_UP.cross(forward)).normalize();
+            }
+            self.update_matrices();
+            return;
+        }
+
+        let radius = radius * (1.0 + padding);
+        let distance = match self.projection {
+            ProjectionKind::Perspective { fovy } => {
+                // The sphere has to fit in the narrower of the two fields of view
+                let half_fovy = fovy / 2.0;
+                let half_fovx = (half_fovy.tan() * self.aspect).atan();
+                radius / half_fovy.min(half_fovx).sin()
+            }
+            ProjectionKind::Orthographic { .. } => {
+                self.projection = ProjectionKind::Orthographic {
+                    height: 2.0 * radius * (1.0 / self.aspect).max(1.0),
+                };
+                radius + self.znear
+            }
+        };
+
+        self.eye = center - self.forward * distance;
+        self.update_matrices();
+    }
+
     pub fn eye(&self) -> glam::Vec3 {
         self.eye
     }
@@ -111,8 +177,16 @@ impl Camera {
         self.aspect
     }
 
-    pub fn fovy(&self) -> f32 {
-        self.fovy
+    // Returns `None` for an orthographic camera
+    pub fn fovy(&self) -> Option<f32> {
+        match self.projection {
+            ProjectionKind::Perspective { fovy } => Some(fovy),
+            ProjectionKind::Orthographic { .. } => None,
+        }
+    }
+
+    pub fn projection(&self) -> ProjectionKind {
+        self.projection
     }
 
     pub fn znear(&self) -> f32 {
@@ -123,6 +197,14 @@ impl Camera {
         self.zfar
     }
 
+    pub fn aperture(&self) -> f32 {
+        self.aperture
+    }
+
+    pub fn focus_distance(&self) -> f32 {
+        self.focus_distance
+    }
+
     pub fn world_to_camera(&self) -> glam::Mat4 {
         self.world_to_camera
     }
@@ -163,7 +245,7 @@ impl Camera {
             self.forward,
             self.up,
             self.aspect,
-            self.fovy,
+            self.projection,
             self.znear,
             self.zfar,
         );
@@ -182,7 +264,7 @@ impl Camera {
         forward: glam::Vec3,
         up: glam::Vec3,
         aspect: f32,
-        fovy: f32,
+        projection: ProjectionKind,
         znear: f32,
         zfar: f32,
     ) -> (glam::Mat4, glam::Mat4, glam::Mat4, glam::Mat4, glam::Mat4) {
@@ -196,15 +278,30 @@ impl Camera {
         );
         let camera_to_world = world_to_camera.inverse();
 
-        let top = znear * (fovy / 2.0).tan();
-        let right = top * aspect;
-
-        let camera_projection = glam::Mat4::from_cols(
-            glam::Vec4::new(znear / right, 0.0, 0.0, 0.0),
-            glam::Vec4::new(0.0, znear / top, 0.0, 0.0),
-            glam::Vec4::new(0.0, 0.0, -(zfar + znear) / (zfar - znear), -1.0),
-            glam::Vec4::new(0.0, 0.0, -(2.0 * zfar * znear) / (zfar - znear), 0.0),
-        );
+        let camera_projection = match projection {
+            ProjectionKind::Perspective { fovy } => {
+                let top = znear * (fovy / 2.0).tan();
+                let right = top * aspect;
+
+                glam::Mat4::from_cols(
+                    glam::Vec4::new(znear / right, 0.0, 0.0, 0.0),
+                    glam::Vec4::new(0.0, znear / top, 0.0, 0.0),
+                    glam::Vec4::new(0.0, 0.0, -(zfar + znear) / (zfar - znear), -1.0),
+                    glam::Vec4::new(0.0, 0.0, -(2.0 * zfar * znear) / (zfar - znear), 0.0),
+                )
+            }
+            ProjectionKind::Orthographic { height } => {
+                let top = height / 2.0;
+                let right = top * aspect;
+
+                glam::Mat4::from_cols(
+                    glam::Vec4::new(1.0 / right, 0.0, 0.0, 0.0),
+                    glam::Vec4::new(0.0, 1.0 / top, 0.0, 0.0),
+                    glam::Vec4::new(0.0, 0.0, -2.0 / (zfar - znear), 0.0),
+                    glam::Vec4::new(0.0, 0.0, -(zfar + znear) / (zfar - znear), 1.0),
+                )
+            }
+        };
         let camera_inverse_projection = camera_projection.inverse();
 
         let view_projection = camera_projection * world_to_camera;
@@ -219,8 +316,29 @@ impl Camera {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraControllerMode {
+    // Right-drag looks around and WASDQE moves
+    Fly,
+    // Left-drag orbits around the focus, middle-drag pans it and scrolling zooms
+    Orbit { focus: Vec3, distance: f32 },
+}
+
+/// Returns the eye position of a camera orbiting `focus` at the given angles in radians.
+/// An azimuth and elevation of zero puts the eye on the +Z side of the focus.
+pub fn orbit_eye(focus: Vec3, distance: f32, azimuth: f32, elevation: f32) -> Vec3 {
+    focus
+        + distance
+            * Vec3::new(
+                elevation.cos() * azimuth.sin(),
+                elevation.sin(),
+                elevation.cos() * azimuth.cos(),
+            )
+}
+
 // Ref: https://sotrh.github.io/learn-wgpu/beginner/tutorial6-uniforms
 pub struct CameraController {
+    mode: CameraControllerMode,
     speed: f32,
     is_shift_pressed: bool,
     is_up_pressed: bool,
@@ -230,14 +348,26 @@ pub struct CameraController {
     is_left_pressed: bool,
     is_right_pressed: bool,
     is_cursor_locked: bool,
+    is_orbiting: bool,
+    is_panning: bool,
     cursor_position: Vec2,
     cursor_delta: Vec2,
+    // In lines
+    scroll_delta: f32,
     sensitivity: f32,
+    // In radians, only used in orbit mode
+    azimuth: f32,
+    elevation: f32,
 }
 
 impl CameraController {
+    const MIN_ORBIT_DISTANCE: f32 = 0.01;
+    // Stops just short of the poles, where the up vector would flip
+    const MAX_ORBIT_ELEVATION: f32 = 89.0_f32.to_radians();
+
     pub fn new(speed: f32) -> Self {
         Self {
+            mode: CameraControllerMode::Fly,
             speed,
             is_shift_pressed: false,
             is_up_pressed: false,
@@ -247,9 +377,14 @@ impl CameraController {
             is_left_pressed: false,
             is_right_pressed: false,
             is_cursor_locked: false,
+            is_orbiting: false,
+            is_panning: false,
             cursor_position: Vec2::ZERO,
             cursor_delta: Vec2::ZERO,
+            scroll_delta: 0.0,
             sensitivity: 0.003,
+            azimuth: 0.0,
+            elevation: 0.0,
         }
     }
 
@@ -262,6 +397,37 @@ impl CameraController {
         self.is_cursor_locked
     }
 
+    pub fn mode(&self) -> CameraControllerMode {
+        self.mode
+    }
+
+    // Returns true while the input needs the camera to be updated
+    pub fn is_controlling_camera(&self) -> bool {
+        match self.mode {
+            CameraControllerMode::Fly => self.is_cursor_locked,
+            CameraControllerMode::Orbit { .. } => {
+                self.is_orbiting || self.is_panning || self.scroll_delta != 0.0
+            }
+        }
+    }
+
+    pub fn set_fly_mode(&mut self) {
+        self.mode = CameraControllerMode::Fly;
+    }
+
+    // Starts orbiting `focus` from wherever the camera currently is
+    pub fn set_orbit_mode(&mut self, focus: Vec3, camera: &Camera) {
+        let offset = camera.eye - focus;
+        let distance = offset.length().max(Self::MIN_ORBIT_DISTANCE);
+
+        self.azimuth = offset.x.atan2(offset.z);
+        self.elevation = (offset.y / distance)
+            .asin()
+            .clamp(-Self::MAX_ORBIT_ELEVATION, Self::MAX_ORBIT_ELEVATION);
+        self.scroll_delta = 0.0;
+        self.mode = CameraControllerMode::Orbit { focus, distance };
+    }
+
     pub fn process_events(&mut self, event: &WindowEvent) {
         match event {
             WindowEvent::KeyboardInput {
@@ -300,9 +466,39 @@ impl CameraController {
                 }
             }
 
-            WindowEvent::MouseInput { state, button, .. } => {
-                if *button == winit::event::MouseButton::Right {
-                    self.is_cursor_locked = state.is_pressed();
+            WindowEvent::MouseInput {
+                state,
+                button: winit::event::MouseButton::Right,
+                ..
+            } => {
+                self.is_cursor_locked = state.is_pressed();
+            }
+
+            WindowEvent::MouseInput {
+                state,
+                button: winit::event::MouseButton::Left,
+                ..
+            } => {
+                self.is_orbiting = state.is_pressed();
+            }
+
+            WindowEvent::MouseInput {
+                state,
+                button: winit::event::MouseButton::Middle,
+                ..
+            } => {
+                self.is_panning = state.is_pressed();
+            }
+
+            WindowEvent::MouseWheel { delta, .. } => {
+                if let CameraControllerMode::Orbit { .. } = self.mode {
+                    self.scroll_delta += match delta {
+                        winit::event::MouseScrollDelta::LineDelta(_, y) => *y,
+                        // A line is roughly 20 pixels
+                        winit::event::MouseScrollDelta::PixelDelta(position) => {
+                            position.y as f32 / 20.0
+                        }
+                    };
                 }
             }
 
@@ -317,6 +513,47 @@ impl CameraController {
     }
 
     pub fn update_camera(&mut self, camera: &mut Camera, delta_time: f32) {
+        match self.mode {
+            CameraControllerMode::Fly => self.update_fly_camera(camera, delta_time),
+            CameraControllerMode::Orbit { focus, distance } => {
+                self.update_orbit_camera(camera, focus, distance)
+            }
+        }
+    }
+
+    fn update_orbit_camera(&mut self, camera: &mut Camera, mut focus: Vec3, mut distance: f32) {
+        let rotation_delta = self.cursor_delta * self.sensitivity;
+
+        if self.is_orbiting {
+            self.azimuth -= rotation_delta.x;
+            self.elevation = (self.elevation + rotation_delta.y)
+                .clamp(-Self::MAX_ORBIT_ELEVATION, Self::MAX_ORBIT_ELEVATION);
+        }
+
+        if self.is_panning {
+            // Pan faster when further away so the focus keeps up with the cursor
+            let right = camera.forward.cross(camera.up).normalize();
+            focus += (camera.up * rotation_delta.y - right * rotation_delta.x) * distance;
+        }
+
+        // Each line scrolled zooms in by 10%
+        distance = (distance * 0.9_f32.powf(self.scroll_delta)).max(Self::MIN_ORBIT_DISTANCE);
+
+        self.cursor_delta = Vec2::ZERO;
+        self.scroll_delta = 0.0;
+        self.mode = CameraControllerMode::Orbit { focus, distance };
+
+        camera.eye = orbit_eye(focus, distance, self.azimuth, self.elevation);
+        camera.forward = (focus - camera.eye).normalize();
+        camera.up = camera
+            .forward
+            .cross(Camera::GLOBAL_UP.cross(camera.forward))
+            .normalize();
+
+        camera.update_matrices();
+    }
+
+    fn update_fly_camera(&mut self, camera: &mut Camera, delta_time: f32) {
         if !self.is_cursor_locked {
             return;
         }
@@ -378,3 +615,116 @@ impl CameraController {
         camera.update_matrices();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unprojects the four corners of the near or far plane in NDC into camera space
+    fn frustum_corners(camera: &Camera, ndc_z: f32) -> [Vec3; 4] {
+        [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(x, y)| {
+            camera
+                .camera_inverse_projection()
+                .project_point3(Vec3::new(x, y, ndc_z))
+        })
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-3), "{a} != {b}");
+    }
+
+    #[test]
+    fn orthographic_frustum_is_a_box_and_perspective_widens() {
+        let mut camera = Camera::new(
+            Vec3::new(0.0, 0.0, 4.0),
+            Vec3::NEG_Z,
+            2.0,
+            std::f32::consts::FRAC_PI_2,
+            1.0,
+            10.0,
+        );
+
+        // A 90 degree field of view is as tall as it is far away
+        let near = frustum_corners(&camera, -1.0);
+        let far = frustum_corners(&camera, 1.0);
+        assert_close(near[2], Vec3::new(2.0, 1.0, -1.0));
+        assert_close(far[2], Vec3::new(20.0, 10.0, -10.0));
+        for (near, far) in near.iter().zip(far) {
+            assert_close(*near * 10.0, far);
+        }
+
+        camera.set_projection(ProjectionKind::Orthographic { height: 4.0 });
+        assert_eq!(camera.fovy(), None);
+
+        let near = frustum_corners(&camera, -1.0);
+        let far = frustum_corners(&camera, 1.0);
+        assert_close(near[2], Vec3::new(4.0, 2.0, -1.0));
+        assert_close(far[2], Vec3::new(4.0, 2.0, -10.0));
+        for (near, far) in near.iter().zip(far) {
+            assert!(near.truncate().abs_diff_eq(far.truncate(), 1e-3));
+        }
+    }
+
+    fn assert_corners_in_view(camera: &Camera, min: Vec3, max: Vec3) {
+        for i in 0..8 {
+            let corner = Vec3::select(
+                glam::BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0),
+                max,
+                min,
+            );
+            let ndc = camera.view_projection().project_point3(corner);
+
+            assert!(
+                ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0 && ndc.z.abs() <= 1.0,
+                "{corner} is at {ndc} in NDC"
+            );
+        }
+    }
+
+    #[test]
+    fn framed_box_corners_are_in_view() {
+        let (min, max) = (Vec3::new(-1.0, 0.0, -3.0), Vec3::new(2.0, 0.5, 1.0));
+
+        for aspect in [0.5, 1.0, 2.0] {
+            for forward in [Vec3::NEG_Z, Vec3::new(1.0, -1.0, 1.0).normalize()] {
+                let mut camera = Camera::new(
+                    Vec3::new(0.0, 0.0, 4.0),
+                    forward,
+                    aspect,
+                    std::f32::consts::FRAC_PI_4,
+                    0.1,
+                    100.0,
+                );
+                camera.frame_bounds(min, max, 0.1);
+                assert_corners_in_view(&camera, min, max);
+
+                camera.set_projection(ProjectionKind::Orthographic { height: 1.0 });
+                camera.frame_bounds(min, max, 0.1);
+                assert_corners_in_view(&camera, min, max);
+            }
+        }
+    }
+
+    #[test]
+    fn orbit_angles_round_trip_through_the_eye_position() {
+        let focus = Vec3::new(1.0, -2.0, 0.5);
+        let mut camera = Camera::new(Vec3::ZERO, Vec3::NEG_Z, 1.0, 1.0, 0.1, 100.0);
+        let mut controller = CameraController::new(1.0);
+
+        for (azimuth, elevation) in [(0.0, 0.0), (1.0, 0.5), (-2.5, -1.2), (3.0, 1.5)] {
+            let eye = orbit_eye(focus, 3.0, azimuth, elevation);
+            camera.set_eye(eye);
+            controller.set_orbit_mode(focus, &camera);
+
+            assert!((controller.azimuth - azimuth).abs() < 1e-4);
+            assert!((controller.elevation - elevation).abs() < 1e-4);
+            let CameraControllerMode::Orbit { distance, .. } = controller.mode() else {
+                panic!("The controller should be orbiting");
+            };
+            assert!(
+                orbit_eye(focus, distance, controller.azimuth, controller.elevation)
+                    .abs_diff_eq(eye, 1e-4)
+            );
+        }
+    }
+}
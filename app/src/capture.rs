@@ -0,0 +1,216 @@
+/// Why a frame couldn't be captured.
+#[derive(Debug)]
+pub enum CaptureError {
+    // The surface doesn't allow copying out of its textures
+    Unsupported,
+    Surface(wgpu::SurfaceError),
+    UnsupportedFormat(wgpu::TextureFormat),
+    Map(wgpu::BufferAsyncError),
+    Image(image::ImageError),
+}
+
+impl std::fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported => write!(f, "the surface can't be copied from"),
+            Self::Surface(error) => write!(f, "{error}"),
+            Self::UnsupportedFormat(format) => write!(f, "can't capture {format:?} textures"),
+            Self::Map(error) => write!(f, "{error}"),
+            Self::Image(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+/// Returns the number of bytes per row of a texture copied into a buffer,
+/// wgpu needs the rows to be padded to `COPY_BYTES_PER_ROW_ALIGNMENT`.
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+}
+
+// Strips the padding off the end of each row and swizzles BGRA pixels to RGBA
+fn unpad_pixels(padded_pixels: &[u8], width: u32, is_bgra: bool) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(padded_pixels.len());
+    for row in padded_pixels.chunks_exact(padded_bytes_per_row(width) as usize) {
+        pixels.extend_from_slice(&row[..(width * 4) as usize]);
+    }
+
+    if is_bgra {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    pixels
+}
+
+/// A texture copied into a mappable buffer, read back into an image once the copy is submitted.
+pub struct FrameCapture {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl FrameCapture {
+    /// Records a copy of the texture, which has to have `COPY_SRC` usage.
+    pub fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> Self {
+        let width = texture.width();
+        let height = texture.height();
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Capture Buffer"),
+            size: (padded_bytes_per_row(width) * height) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row(width)),
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+
+        Self {
+            buffer,
+            width,
+            height,
+            format: texture.format(),
+        }
+    }
+
+    /// Blocks until the copy finishes, then returns its pixels without the row padding.
+    pub fn read(self, device: &wgpu::Device) -> Result<image::RgbaImage, CaptureError> {
+        // Only 8-bit formats are read back, so their bytes can be moved into the image as-is
+        let is_bgra = match self.format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            format => return Err(CaptureError::UnsupportedFormat(format)),
+        };
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .expect("The buffer map callback should run after waiting on the device")
+            .map_err(CaptureError::Map)?;
+
+        let pixels = unpad_pixels(
+            &self.buffer.slice(..).get_mapped_range(),
+            self.width,
+            is_bgra,
+        );
+        self.buffer.unmap();
+
+        Ok(image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .expect("The pixels should fill the image"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn rows_are_padded_to_the_copy_alignment() {
+        assert_eq!(padded_bytes_per_row(1), 256);
+        assert_eq!(padded_bytes_per_row(64), 256);
+        assert_eq!(padded_bytes_per_row(65), 512);
+    }
+
+    #[test]
+    fn padding_is_stripped_and_bgra_is_swizzled() {
+        // Two rows of two pixels, followed by padding that shouldn't end up in the image
+        let mut padded_pixels = vec![0xAA; 2 * 256];
+        padded_pixels[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        padded_pixels[256..264].copy_from_slice(&[9, 10, 11, 12, 13, 14, 15, 16]);
+
+        assert_eq!(
+            unpad_pixels(&padded_pixels, 2, false),
+            [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]
+        );
+        assert_eq!(
+            unpad_pixels(&padded_pixels, 2, true),
+            [3, 2, 1, 4, 7, 6, 5, 8, 11, 10, 9, 12, 15, 14, 13, 16]
+        );
+    }
+
+    #[test]
+    fn cleared_texture_is_read_back() {
+        let Some((_, device, queue)) = test_utils::device(wgpu::Features::empty()) else {
+            return;
+        };
+
+        for format in [
+            wgpu::TextureFormat::Rgba8Unorm,
+            wgpu::TextureFormat::Bgra8Unorm,
+        ] {
+            // Wider than one aligned row, so the rows are padded
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Test Texture"),
+                size: wgpu::Extent3d {
+                    width: 65,
+                    height: 3,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Test Command Encoder"),
+            });
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Test Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            r: 1.0,
+                            g: 0.0,
+                            b: 0.0,
+                            a: 1.0,
+                        }),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            let capture = FrameCapture::new(&device, &mut encoder, &texture);
+            queue.submit(Some(encoder.finish()));
+            let image = capture.read(&device).unwrap();
+
+            assert_eq!(image.dimensions(), (65, 3));
+            assert!(
+                image.pixels().all(|pixel| pixel.0 == [255, 0, 0, 255]),
+                "{format:?}"
+            );
+        }
+    }
+}
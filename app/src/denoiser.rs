@@ -0,0 +1,315 @@
+use wgpu::util::DeviceExt;
+
+use crate::{
+    raytracer::{
+        create_raytracer_result_texture, raytracer_workgroup_count, RaytracerAccumulation,
+    },
+    renderer::RenderStats,
+};
+
+/// The number of à-trous passes, the step width doubles with each one
+/// so the filter covers a 61x61 pixel footprint by the last pass.
+pub const DENOISER_ITERATIONS: usize = 4;
+
+// How much a color difference is tolerated before a tap stops contributing,
+// it's halved every pass so the wider passes blur less across edges
+const DENOISER_COLOR_PHI: f32 = 1.0;
+
+// Matches the `Pass` struct in the denoise shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DenoiserPassUniform {
+    step_width: u32,
+    color_phi: f32,
+    _padding: [u32; 2],
+}
+
+/// Creates the pair of textures the denoiser passes ping-pong between,
+/// in the same format as the raytracer result texture.
+pub fn create_denoiser_textures(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    accumulation: RaytracerAccumulation,
+) -> [(wgpu::Texture, wgpu::TextureView); 2] {
+    [
+        create_raytracer_result_texture(device, width, height, accumulation),
+        create_raytracer_result_texture(device, width, height, accumulation),
+    ]
+}
+
+#[allow(clippy::type_complexity)]
+pub fn initialize_denoiser(
+    accumulation: RaytracerAccumulation,
+    result_texture_view: &wgpu::TextureView,
+    texture_views: [&wgpu::TextureView; 2],
+    raytracer_render_bind_group_layout: &wgpu::BindGroupLayout,
+    tonemap_uniform_buffer: &wgpu::Buffer,
+    device: &wgpu::Device,
+) -> (
+    Vec<wgpu::Buffer>,
+    wgpu::BindGroupLayout,
+    Vec<wgpu::BindGroup>,
+    wgpu::BindGroup,
+    wgpu::ComputePipeline,
+) {
+    // Replace the storage texture formats to match the result texture
+    let denoise_shader_source = include_str!("shaders/raytracer/denoise.wgsl").replace(
+        "texture_storage_2d<rgba8unorm",
+        &format!("texture_storage_2d<{}", accumulation.wgsl_format()),
+    );
+    let denoise_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Denoise Shader"),
+        source: wgpu::ShaderSource::Wgsl(denoise_shader_source.into()),
+    });
+
+    let pass_uniform_buffers = (0..DENOISER_ITERATIONS)
+        .map(|iteration| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Denoiser Pass Uniform Buffer"),
+                usage: wgpu::BufferUsages::UNIFORM,
+                contents: bytemuck::cast_slice(&[DenoiserPassUniform {
+                    step_width: 1 << iteration,
+                    color_phi: DENOISER_COLOR_PHI / (1 << iteration) as f32,
+                    _padding: [0; 2],
+                }]),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let denoise_bind_group_layout =
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Denoise Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        format: accumulation.texture_format(),
+                        access: wgpu::StorageTextureAccess::ReadOnly,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        format: accumulation.texture_format(),
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+    let (denoise_bind_groups, denoiser_render_bind_group) = create_denoiser_bind_groups(
+        result_texture_view,
+        texture_views,
+        device,
+        &denoise_bind_group_layout,
+        raytracer_render_bind_group_layout,
+        &pass_uniform_buffers,
+        tonemap_uniform_buffer,
+    );
+
+    let denoise_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Denoise Pipeline Layout"),
+        bind_group_layouts: &[&denoise_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    let denoise_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Denoise Pipeline"),
+        layout: Some(&denoise_pipeline_layout),
+        module: &denoise_shader,
+        entry_point: "main",
+        compilation_options: Default::default(),
+        cache: None,
+    });
+
+    (
+        pass_uniform_buffers,
+        denoise_bind_group_layout,
+        denoise_bind_groups,
+        denoiser_render_bind_group,
+        denoise_pipeline,
+    )
+}
+
+/// Creates a bind group for each denoiser pass, plus one that lets the raytracer's
+/// render pipeline draw the output of the last pass.
+pub fn create_denoiser_bind_groups(
+    result_texture_view: &wgpu::TextureView,
+    texture_views: [&wgpu::TextureView; 2],
+    device: &wgpu::Device,
+    denoise_bind_group_layout: &wgpu::BindGroupLayout,
+    raytracer_render_bind_group_layout: &wgpu::BindGroupLayout,
+    pass_uniform_buffers: &[wgpu::Buffer],
+    tonemap_uniform_buffer: &wgpu::Buffer,
+) -> (Vec<wgpu::BindGroup>, wgpu::BindGroup) {
+    // The first pass filters the result texture, the rest alternate between the two textures
+    let denoise_bind_groups = pass_uniform_buffers
+        .iter()
+        .enumerate()
+        .map(|(iteration, pass_uniform_buffer)| {
+            let source = if iteration == 0 {
+                result_texture_view
+            } else {
+                texture_views[(iteration - 1) % 2]
+            };
+
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Denoise Bind Group"),
+                layout: denoise_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(texture_views[iteration % 2]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: pass_uniform_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        })
+        .collect();
+
+    let denoiser_render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Denoiser Render Bind Group"),
+        layout: raytracer_render_bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(
+                    texture_views[(DENOISER_ITERATIONS - 1) % 2],
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: tonemap_uniform_buffer.as_entire_binding(),
+            },
+        ],
+    });
+
+    (denoise_bind_groups, denoiser_render_bind_group)
+}
+
+pub fn run_denoiser(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    window_size: winit::dpi::PhysicalSize<u32>,
+    denoise_bind_groups: &[wgpu::BindGroup],
+    denoise_pipeline: &wgpu::ComputePipeline,
+    timestamp_writes: Option<wgpu::ComputePassTimestampWrites>,
+    render_stats: &mut RenderStats,
+) {
+    let mut compute_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Denoise Command Encoder"),
+    });
+
+    {
+        let mut denoise_cpass = compute_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Denoise Compute Pass"),
+            timestamp_writes,
+        });
+
+        denoise_cpass.set_pipeline(denoise_pipeline);
+        let (workgroups_x, workgroups_y) =
+            raytracer_workgroup_count(window_size.width, window_size.height);
+
+        // Dispatches in the same pass are synchronized, so each one sees the previous one's writes
+        for denoise_bind_group in denoise_bind_groups {
+            denoise_cpass.set_bind_group(0, denoise_bind_group, &[]);
+            denoise_cpass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+            render_stats.record_dispatch(workgroups_x, workgroups_y, 1);
+        }
+    }
+
+    queue.submit(Some(compute_encoder.finish()));
+}
+
+/// An edge-avoiding à-trous filter over the raytracer result texture. The raytracer already
+/// accumulates its samples over time, this smooths out the noise left in the first few frames.
+pub struct Denoiser {
+    pub textures: [wgpu::Texture; 2],
+    pub texture_views: [wgpu::TextureView; 2],
+    pub pass_uniform_buffers: Vec<wgpu::Buffer>,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_groups: Vec<wgpu::BindGroup>,
+    pub render_bind_group: wgpu::BindGroup,
+    pub compute_pipeline: wgpu::ComputePipeline,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{raytracer::RaytracerSettings, test_utils};
+
+    #[test]
+    fn denoiser_builds_and_runs_for_each_accumulation() {
+        for accumulation in [
+            RaytracerAccumulation::Compatible,
+            RaytracerAccumulation::F16,
+        ] {
+            let Some(renderer) =
+                test_utils::headless_renderer(16, 16, accumulation, &RaytracerSettings::default())
+            else {
+                return;
+            };
+            let raytracer = renderer.raytracer.as_ref().unwrap();
+            // Adapters without read-write f16 storage fall back to the compatible format
+            if raytracer.accumulation != accumulation {
+                continue;
+            }
+            let device = &renderer.wgpu.device;
+
+            device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let [(_, texture_view_a), (_, texture_view_b)] =
+                create_denoiser_textures(device, 16, 16, accumulation);
+            let (_, _, bind_groups, _, compute_pipeline) = initialize_denoiser(
+                accumulation,
+                &raytracer.result_texture_view,
+                [&texture_view_a, &texture_view_b],
+                &raytracer.render_bind_group_layout,
+                &raytracer.tonemap_uniform_buffer,
+                device,
+            );
+            let mut render_stats = RenderStats::default();
+            run_denoiser(
+                device,
+                &renderer.wgpu.queue,
+                winit::dpi::PhysicalSize::new(16, 16),
+                &bind_groups,
+                &compute_pipeline,
+                None,
+                &mut render_stats,
+            );
+            let error = pollster::block_on(device.pop_error_scope());
+
+            assert!(error.is_none(), "{accumulation:?}: {error:?}");
+            assert_eq!(bind_groups.len(), DENOISER_ITERATIONS);
+            // Each pass covers the 16x16 texture with 2x2 workgroups
+            assert_eq!(
+                render_stats.compute_workgroups,
+                4 * DENOISER_ITERATIONS as u32
+            );
+        }
+    }
+}
@@ -28,14 +28,16 @@ pub struct RendererEguiResources {
     pub state: egui_winit::State,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn render_egui(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     render_encoder: &mut wgpu::CommandEncoder,
     surface_texture_view: &wgpu::TextureView,
     renderer: &mut egui_wgpu::Renderer,
-    egui_primitives: &Vec<egui::ClippedPrimitive>,
+    egui_primitives: &[egui::ClippedPrimitive],
     egui_screen_descriptor: &egui_wgpu::ScreenDescriptor,
+    timestamp_writes: Option<wgpu::RenderPassTimestampWrites>,
 ) {
     renderer.update_buffers(
         device,
@@ -48,7 +50,7 @@ pub fn render_egui(
     let egui_rpass = render_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
         label: Some("Rasterizer Render Pass"),
         color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-            view: &surface_texture_view,
+            view: surface_texture_view,
             resolve_target: None,
             ops: wgpu::Operations {
                 load: wgpu::LoadOp::Load,
@@ -56,13 +58,13 @@ pub fn render_egui(
             },
         })],
         depth_stencil_attachment: None,
-        timestamp_writes: None,
+        timestamp_writes,
         occlusion_query_set: None,
     });
 
     renderer.render(
         &mut egui_rpass.forget_lifetime(),
-        &egui_primitives,
-        &egui_screen_descriptor,
+        egui_primitives,
+        egui_screen_descriptor,
     );
 }
@@ -7,19 +7,29 @@ use web_time::Instant;
 use std::time::Instant;
 
 use glam::Vec3;
-use winit::{event::WindowEvent, window::Window};
+use winit::{
+    event::{ElementState, WindowEvent},
+    window::Window,
+};
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::capture::{CaptureError, FrameCapture};
 use crate::{
-    camera::{Camera, CameraController},
-    egui::render_egui,
+    camera::{Camera, CameraController, CameraControllerMode},
+    denoiser::{create_denoiser_textures, run_denoiser},
+    egui::{initialize_egui, render_egui},
+    frustum::Frustum,
+    gpu_timer::GpuPass,
+    grid::{GridSpacing, GridUniform},
     lights,
+    mesh::{primitives::Primitive, Mesh},
     rasterizer::render_rasterizer,
     raytracer::{
-        create_raytracer_bind_groups, create_raytracer_result_texture, render_raytracer,
-        run_raytracer,
+        create_raytracer_result_texture, render_raytracer, run_raytracer, CameraLensUniform,
+        RaytracerAccumulation, RaytracerSettings, TonemapUniform,
     },
-    renderer::Renderer,
-    wgpu::update_buffer,
+    renderer::{RenderStats, Renderer},
+    wgpu::{update_buffer, WgpuOptions},
 };
 
 pub struct Engine {
@@ -30,7 +40,10 @@ pub struct Engine {
     pub camera_controller: CameraController,
     last_frame_time: Instant,
     delta_time: f32,
+    render_stats: RenderStats,
+    last_render_stats: RenderStats,
     pub renderer: Renderer,
+    pub egui: crate::egui::RendererEguiResources,
     // The window must be declared after the wgpu surface so
     // it gets dropped after it as the surface contains
     // unsafe references to the window's resources.
@@ -38,14 +51,41 @@ pub struct Engine {
     pub window_size: winit::dpi::PhysicalSize<u32>,
     is_raytracer_enabled: bool,
     raytracer_max_frames: u32,
+    raytracer_settings: RaytracerSettings,
+    raytracer_exposure: f32,
+    is_denoiser_enabled: bool,
+    is_frustum_culling_enabled: bool,
+    is_wireframe_enabled: bool,
+    is_grid_enabled: bool,
+    grid_fade_distance: f32,
+    grid_spacing: GridSpacing,
     color_uniform: [f32; 4],
-    sun_light: lights::DirectionalLight,
-    sun_azi_alt: (f32, f32),
+    clear_color: [f32; 3],
+    // Azimuth and altitude of each directional light, in degrees
+    directional_light_azi_alts: Vec<(f32, f32)>,
+    point_lights: Vec<lights::PointLight>,
+    #[cfg(not(target_arch = "wasm32"))]
+    environment_map_path: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    environment_map_error: Option<String>,
+    #[cfg(not(target_arch = "wasm32"))]
+    mesh_error: Option<String>,
+    // Set by `capture_frame` for the frame it renders
+    #[cfg(not(target_arch = "wasm32"))]
+    is_frame_capture_requested: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    frame_capture: Option<FrameCapture>,
+    // The browser already paces frames with requestAnimationFrame, so this is native only
+    #[cfg(not(target_arch = "wasm32"))]
+    is_fps_limit_enabled: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    fps_limit: f32,
+    raytracer_accumulation_error: Option<String>,
 }
 
 impl Engine {
     // Creating some of the wgpu types requires async code
-    pub async fn new(window: Arc<Window>) -> Engine {
+    pub async fn new(window: Arc<Window>, wgpu_options: WgpuOptions) -> Engine {
         let mut window_size = window.inner_size();
         window_size.width = window_size.width.max(1);
         window_size.height = window_size.height.max(1);
@@ -64,19 +104,39 @@ impl Engine {
         let camera_controller = CameraController::new(0.8);
 
         let color_uniform = [1.0, 1.0, 1.0, 1.0];
+        let clear_color = [0.0; 3];
+        let grid_fade_distance = 20.0;
+        let grid_spacing = GridSpacing::default();
+        let mut raytracer_settings = RaytracerSettings::default();
+        raytracer_settings.background_color = clear_color;
+        let raytracer_exposure = 1.0;
 
-        let sun_azi_alt = (45.0, 45.0);
-        let sun_light = lights::DirectionalLight::from_azi_alt(sun_azi_alt.0, sun_azi_alt.1);
+        let directional_light_azi_alts = vec![(45.0, 45.0)];
+        let point_lights = Vec::new();
 
         let renderer = Renderer::new(
             window.clone(),
             &window_size,
             &camera,
             &color_uniform,
-            &sun_light,
+            &Self::directional_lights(&directional_light_azi_alts),
+            &point_lights,
+            RaytracerAccumulation::default(),
+            &raytracer_settings,
+            raytracer_exposure,
+            grid_fade_distance,
+            grid_spacing,
+            &wgpu_options,
         )
         .await;
 
+        let (egui_renderer, egui_state) = initialize_egui(
+            &window,
+            &renderer.wgpu.device,
+            &renderer.wgpu.surface_config,
+            window.scale_factor() as f32,
+        );
+
         Self {
             frame_count: 0,
             target_frame_time: 1.0 / 120.0,
@@ -85,18 +145,53 @@ impl Engine {
             camera_controller,
             last_frame_time: Instant::now(),
             delta_time: 0.0,
-            window: window,
+            render_stats: RenderStats::default(),
+            last_render_stats: RenderStats::default(),
+            window,
             window_size,
             is_raytracer_enabled: false,
             raytracer_max_frames: 256,
+            raytracer_settings,
+            raytracer_exposure,
+            is_denoiser_enabled: false,
+            is_frustum_culling_enabled: true,
+            is_wireframe_enabled: false,
+            is_grid_enabled: false,
+            grid_fade_distance,
+            grid_spacing,
             color_uniform,
-            sun_azi_alt,
-            sun_light,
+            clear_color,
+            directional_light_azi_alts,
+            point_lights,
+            #[cfg(not(target_arch = "wasm32"))]
+            environment_map_path: String::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            environment_map_error: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            mesh_error: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            is_frame_capture_requested: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            frame_capture: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            is_fps_limit_enabled: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            fps_limit: 60.0,
+            raytracer_accumulation_error: None,
             renderer,
+            egui: crate::egui::RendererEguiResources {
+                renderer: egui_renderer,
+                state: egui_state,
+            },
         }
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        // Minimized windows are zero sized but textures can't be, so everything keeps its size until it's restored
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+
         self.window_size = new_size;
 
         // Update camera
@@ -107,26 +202,36 @@ impl Engine {
         Self::reset_frame_count(
             &mut self.frame_count,
             &self.renderer.wgpu,
-            &self.renderer.raytracer,
+            self.renderer.raytracer.as_ref().ok(),
         );
 
         // Recreate the raytracer result texture with the new size
-        let (raytracer_result_texture, raytracer_result_texture_view) =
-            create_raytracer_result_texture(
-                &self.renderer.wgpu.device,
-                new_size.width,
-                new_size.height,
-            );
-        self.renderer.raytracer.result_texture = raytracer_result_texture;
-        self.renderer.raytracer.result_texture_view = raytracer_result_texture_view;
+        if let Ok(raytracer) = &mut self.renderer.raytracer {
+            let (raytracer_result_texture, raytracer_result_texture_view) =
+                create_raytracer_result_texture(
+                    &self.renderer.wgpu.device,
+                    new_size.width,
+                    new_size.height,
+                    raytracer.accumulation,
+                );
+            raytracer.result_texture = raytracer_result_texture;
+            raytracer.result_texture_view = raytracer_result_texture_view;
+
+            let [(denoiser_texture_a, denoiser_texture_view_a), (denoiser_texture_b, denoiser_texture_view_b)] =
+                create_denoiser_textures(
+                    &self.renderer.wgpu.device,
+                    new_size.width,
+                    new_size.height,
+                    raytracer.accumulation,
+                );
+            raytracer.denoiser.textures = [denoiser_texture_a, denoiser_texture_b];
+            raytracer.denoiser.texture_views = [denoiser_texture_view_a, denoiser_texture_view_b];
+        }
 
         // Reconfigure the surface with the new size
-        self.renderer.wgpu.surface_config.width = new_size.width.max(1);
-        self.renderer.wgpu.surface_config.height = new_size.height.max(1);
-        self.renderer.wgpu.surface.configure(
-            &self.renderer.wgpu.device,
-            &self.renderer.wgpu.surface_config,
-        );
+        self.renderer.wgpu.surface_config.width = new_size.width;
+        self.renderer.wgpu.surface_config.height = new_size.height;
+        self.renderer.wgpu.configure_target();
 
         self.renderer.rasterizer.depth_texture = crate::wgpu::Texture::create_depth_texture(
             &self.renderer.wgpu.device,
@@ -135,27 +240,7 @@ impl Engine {
         );
 
         // Recreate the raytracer bind groups with the new texture view
-        let (raytracer_render_bind_group, raytracer_compute_bind_group) =
-            create_raytracer_bind_groups(
-                &self.renderer.raytracer.result_texture_view,
-                &self.renderer.wgpu.device,
-                &self.renderer.raytracer.render_bind_group_layout,
-                &self.renderer.raytracer.compute_bind_group_layout,
-                &self.renderer.vertex_buffer,
-                &self.renderer.index_buffer,
-                &self.renderer.raytracer.frame_count_uniform_buffer,
-                &self.renderer.raytracer.vertex_stride_uniform_buffer,
-                &self.renderer.raytracer.vertex_color_offset_uniform_buffer,
-                &self.renderer.raytracer.vertex_normal_offset_uniform_buffer,
-                &self.renderer.raytracer.camera_to_world_uniform_buffer,
-                &self
-                    .renderer
-                    .raytracer
-                    .camera_inverse_projection_uniform_buffer,
-                &self.renderer.raytracer.sun_direction_uniform_buffer,
-            );
-        self.renderer.raytracer.render_bind_group = raytracer_render_bind_group;
-        self.renderer.raytracer.compute_bind_group = raytracer_compute_bind_group;
+        self.renderer.recreate_raytracer_bind_groups();
 
         // On macOS the window needs to be redrawn manually after resizing
         #[cfg(target_os = "macos")]
@@ -165,20 +250,63 @@ impl Engine {
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        if self.is_raytracer_enabled && self.frame_count < self.raytracer_max_frames {
-            run_raytracer(
-                &self.renderer.wgpu.device,
-                &self.renderer.wgpu.queue,
-                self.window_size,
-                &self.renderer.raytracer.compute_bind_group,
-                &self.renderer.raytracer.compute_pipeline,
-            );
+        // Sleep off the rest of the frame if the last one started too recently
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.is_fps_limit_enabled {
+            std::thread::sleep(crate::utils::frame_pacing_delay(
+                self.fps_limit,
+                self.last_frame_time.elapsed(),
+            ));
+        }
 
-            Self::increment_frame_count(
-                &mut self.frame_count,
-                &self.renderer.wgpu,
-                &self.renderer.raytracer,
-            );
+        self.render_stats.reset();
+
+        if let Some(gpu_timer) = &mut self.renderer.gpu_timer {
+            gpu_timer.poll(&self.renderer.wgpu.device);
+        }
+
+        // WebGPU only reports whether the last raytracer recreation validated some time after it
+        #[cfg(target_arch = "wasm32")]
+        if let Some(error) = self.renderer.take_raytracer_validation_error() {
+            log::error!("Failed to recreate the raytracer: {}", error);
+            self.is_raytracer_enabled = false;
+            self.raytracer_accumulation_error = Some(error);
+        }
+
+        if let (true, Ok(raytracer)) = (self.is_raytracer_enabled, &self.renderer.raytracer) {
+            if raytracer
+                .accumulation
+                .is_accumulating(self.frame_count, self.raytracer_max_frames)
+            {
+                run_raytracer(
+                    &self.renderer.wgpu.device,
+                    &self.renderer.wgpu.queue,
+                    self.window_size,
+                    &raytracer.compute_bind_group,
+                    &raytracer.compute_pipeline,
+                    self.renderer.gpu_timer.as_mut().map(|gpu_timer| {
+                        gpu_timer.compute_pass_timestamp_writes(GpuPass::Raytracer)
+                    }),
+                    &mut self.render_stats,
+                );
+
+                Self::increment_frame_count(&mut self.frame_count, &self.renderer.wgpu, raytracer);
+            }
+
+            // Filtered every frame so toggling the denoiser on after the image converged still works
+            if self.is_denoiser_enabled {
+                run_denoiser(
+                    &self.renderer.wgpu.device,
+                    &self.renderer.wgpu.queue,
+                    self.window_size,
+                    &raytracer.denoiser.bind_groups,
+                    &raytracer.denoiser.compute_pipeline,
+                    self.renderer.gpu_timer.as_mut().map(|gpu_timer| {
+                        gpu_timer.compute_pass_timestamp_writes(GpuPass::Denoiser)
+                    }),
+                    &mut self.render_stats,
+                );
+            }
         }
 
         // Update delta time
@@ -188,7 +316,12 @@ impl Engine {
             .as_secs_f32();
         self.last_frame_time = current_time;
 
-        let surface_texture = self.renderer.wgpu.surface.get_current_texture()?;
+        let surface_texture = self
+            .renderer
+            .wgpu
+            .surface()
+            .expect("The engine always renders to its window")
+            .get_current_texture()?;
 
         let surface_texture_view = surface_texture
             .texture
@@ -201,10 +334,16 @@ impl Engine {
                     label: Some("Render Command Encoder"),
                 });
 
-        let egui_raw_input = self.renderer.egui.state.take_egui_input(&self.window);
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut is_environment_map_load_requested = false;
+        let mut requested_raytracer_accumulation = None;
+        let mut requested_rasterizer_culling = None;
+        let mut requested_present_mode = None;
+        let mut requested_primitive = None;
+
+        let egui_raw_input = self.egui.state.take_egui_input(&self.window);
         let egui_full_output =
-            self.renderer
-                .egui
+            self.egui
                 .state
                 .egui_ctx()
                 .run(egui_raw_input, |egui_ctx: &egui::Context| {
@@ -216,79 +355,611 @@ impl Engine {
                         .show(egui_ctx, |ui| {
                             ui.label(format!("Frame Time: {:.2}ms", self.delta_time * 1000.0));
                             ui.label(format!("FPS: {:.1}", 1.0 / self.delta_time));
+                            ui.label(format!(
+                                "Adapter: {}",
+                                self.renderer.wgpu.adapter.get_info().name
+                            ));
 
                             if self.is_raytracer_enabled {
                                 ui.label(format!("Frame Count: {}", self.frame_count));
                             }
+
+                            // The current frame is still being recorded, so show the last one.
+                            // Only the scene is counted, the UI's own draws aren't.
+                            ui.label(format!(
+                                "Scene Draw Calls: {}",
+                                self.last_render_stats.draw_calls
+                            ));
+                            ui.label(format!(
+                                "Scene Instances: {}",
+                                self.last_render_stats.instances
+                            ));
+                            ui.label(format!(
+                                "Workgroups: {}",
+                                self.last_render_stats.compute_workgroups
+                            ));
+
+                            if let Some(gpu_timer) = &self.renderer.gpu_timer {
+                                for (pass, time) in gpu_timer.pass_times() {
+                                    ui.label(format!("{}: {:.2}ms", pass.name(), time));
+                                }
+                            }
                         });
 
-                    egui::CentralPanel::default()
-                        .frame(egui::Frame::none().inner_margin(egui::Margin::same(10.0)))
+                    // An area instead of a central panel, so the rest of the window isn't counted as UI
+                    egui::Area::new(egui::Id::new("controls"))
+                        .order(egui::Order::Middle)
+                        .fixed_pos(egui::Pos2::ZERO)
                         .show(egui_ctx, |ui| {
-                            if ui
-                                .color_edit_button_rgba_unmultiplied(&mut self.color_uniform)
-                                .changed()
-                            {
-                                update_buffer(
-                                    &self.renderer.wgpu.queue,
-                                    &self.renderer.rasterizer.color_uniform_buffer,
-                                    &self.color_uniform,
-                                );
-                            }
+                            egui::Frame::none()
+                                .inner_margin(egui::Margin::same(10.0))
+                                .show(ui, |ui| {
+                                    if ui
+                                        .color_edit_button_rgba_unmultiplied(
+                                            &mut self.color_uniform,
+                                        )
+                                        .changed()
+                                    {
+                                        update_buffer(
+                                            &self.renderer.wgpu.queue,
+                                            &self.renderer.rasterizer.color_uniform_buffer,
+                                            &self.color_uniform,
+                                        );
+                                    }
 
-                            let sun_azi_changed = ui
-                                .add(
-                                    egui::Slider::new(&mut self.sun_azi_alt.0, 0.0..=360.0)
-                                        .text("Sun Azimuth"),
-                                )
-                                .changed();
-
-                            let sun_alt_changed = ui
-                                .add(
-                                    egui::Slider::new(&mut self.sun_azi_alt.1, 0.0..=90.0)
-                                        .text("Sun Altitude"),
-                                )
-                                .changed();
-
-                            if sun_azi_changed || sun_alt_changed {
-                                self.sun_light = lights::DirectionalLight::from_azi_alt(
-                                    self.sun_azi_alt.0,
-                                    self.sun_azi_alt.1,
-                                );
-
-                                update_buffer(
-                                    &self.renderer.wgpu.queue,
-                                    &self.renderer.rasterizer.sun_direction_uniform_buffer,
-                                    &self.sun_light.direction.to_array(),
-                                );
-                                update_buffer(
-                                    &self.renderer.wgpu.queue,
-                                    &self.renderer.raytracer.sun_direction_uniform_buffer,
-                                    &self.sun_light.direction.to_array(),
-                                );
-
-                                Self::reset_frame_count(
-                                    &mut self.frame_count,
-                                    &self.renderer.wgpu,
-                                    &self.renderer.raytracer,
-                                );
-                            }
+                                    let mut is_raytracer_background_changed = false;
+                                    ui.horizontal(|ui| {
+                                        if ui.color_edit_button_rgb(&mut self.clear_color).changed()
+                                        {
+                                            self.raytracer_settings.background_color =
+                                                self.clear_color;
+                                            is_raytracer_background_changed =
+                                                self.raytracer_settings.is_background_color_enabled
+                                                    != 0;
+                                        }
+                                        ui.label("Clear Color");
+                                    });
 
-                            // Run the raytracer when the checkbox is toggled on
-                            if ui
-                                .checkbox(&mut self.is_raytracer_enabled, "Raytracing")
-                                .changed()
-                            {
-                                Self::reset_frame_count(
-                                    &mut self.frame_count,
-                                    &self.renderer.wgpu,
-                                    &self.renderer.raytracer,
-                                );
-                            }
+                                    let mut is_clear_color_background_enabled =
+                                        self.raytracer_settings.is_background_color_enabled != 0;
+                                    if ui
+                                        .checkbox(
+                                            &mut is_clear_color_background_enabled,
+                                            "Use Clear Color as Raytracer Background",
+                                        )
+                                        .changed()
+                                    {
+                                        self.raytracer_settings.is_background_color_enabled =
+                                            is_clear_color_background_enabled as u32;
+                                        is_raytracer_background_changed = true;
+                                    }
+
+                                    if let (true, Ok(raytracer)) =
+                                        (is_raytracer_background_changed, &self.renderer.raytracer)
+                                    {
+                                        update_buffer(
+                                            &self.renderer.wgpu.queue,
+                                            &raytracer.settings_uniform_buffer,
+                                            &[self.raytracer_settings],
+                                        );
+
+                                        Self::reset_frame_count(
+                                            &mut self.frame_count,
+                                            &self.renderer.wgpu,
+                                            Some(raytracer),
+                                        );
+                                    }
+
+                                    let mut present_mode =
+                                        self.renderer.wgpu.surface_config.present_mode;
+                                    egui::ComboBox::from_label("Present Mode")
+                                        .selected_text(format!("{present_mode:?}"))
+                                        .show_ui(ui, |ui| {
+                                            let supported_present_modes = self
+                                                .renderer
+                                                .wgpu
+                                                .surface()
+                                                .map_or_else(Vec::new, |surface| {
+                                                    surface
+                                                        .get_capabilities(
+                                                            &self.renderer.wgpu.adapter,
+                                                        )
+                                                        .present_modes
+                                                });
+                                            for mode in supported_present_modes {
+                                                ui.selectable_value(
+                                                    &mut present_mode,
+                                                    mode,
+                                                    format!("{mode:?}"),
+                                                );
+                                            }
+                                        });
+                                    if present_mode
+                                        != self.renderer.wgpu.surface_config.present_mode
+                                    {
+                                        requested_present_mode = Some(present_mode);
+                                    }
+
+                                    #[cfg(not(target_arch = "wasm32"))]
+                                    {
+                                        ui.checkbox(&mut self.is_fps_limit_enabled, "Limit FPS");
+                                        ui.add_enabled(
+                                            self.is_fps_limit_enabled,
+                                            egui::Slider::new(&mut self.fps_limit, 10.0..=240.0)
+                                                .text("FPS Limit"),
+                                        );
+                                    }
+
+                                    let mut is_orbit_camera_enabled = matches!(
+                                        self.camera_controller.mode(),
+                                        CameraControllerMode::Orbit { .. }
+                                    );
+                                    if ui
+                                        .checkbox(&mut is_orbit_camera_enabled, "Orbit Camera")
+                                        .changed()
+                                    {
+                                        if is_orbit_camera_enabled {
+                                            let (mesh_min, mesh_max) = self.renderer.mesh_bounds;
+                                            self.camera_controller.set_orbit_mode(
+                                                (mesh_min + mesh_max) / 2.0,
+                                                &self.camera,
+                                            );
+                                        } else {
+                                            self.camera_controller.set_fly_mode();
+                                        }
+                                    }
+
+                                    ui.checkbox(
+                                        &mut self.is_frustum_culling_enabled,
+                                        "Frustum Culling",
+                                    );
+                                    ui.checkbox(&mut self.is_wireframe_enabled, "Wireframe");
+
+                                    // The render pipeline is rebuilt afterwards, once the renderer is free
+                                    let mut culling = self.renderer.rasterizer.culling;
+                                    let mut is_backface_culling_enabled =
+                                        culling.cull_mode.is_some();
+                                    if ui
+                                        .checkbox(
+                                            &mut is_backface_culling_enabled,
+                                            "Backface Culling",
+                                        )
+                                        .changed()
+                                    {
+                                        culling.cull_mode =
+                                            is_backface_culling_enabled.then_some(wgpu::Face::Back);
+                                    }
+
+                                    let mut is_clockwise_front_face_enabled =
+                                        culling.front_face == wgpu::FrontFace::Cw;
+                                    if ui
+                                        .checkbox(
+                                            &mut is_clockwise_front_face_enabled,
+                                            "Clockwise Front Faces",
+                                        )
+                                        .changed()
+                                    {
+                                        culling.front_face = if is_clockwise_front_face_enabled {
+                                            wgpu::FrontFace::Cw
+                                        } else {
+                                            wgpu::FrontFace::Ccw
+                                        };
+                                    }
+
+                                    if culling != self.renderer.rasterizer.culling {
+                                        requested_rasterizer_culling = Some(culling);
+                                    }
+                                    ui.checkbox(&mut self.is_grid_enabled, "Grid");
+                                    let mut is_grid_changed = ui
+                                        .add_enabled(
+                                            self.is_grid_enabled,
+                                            egui::Slider::new(
+                                                &mut self.grid_fade_distance,
+                                                1.0..=100.0,
+                                            )
+                                            .text("Grid Fade Distance"),
+                                        )
+                                        .changed();
+
+                                    // The fixed spacing starts from whatever the adaptive spacing currently is
+                                    let (mut grid_fixed_spacing, _) =
+                                        self.grid_spacing.line_spacing(self.camera.eye().y);
+                                    let mut is_grid_spacing_adaptive =
+                                        self.grid_spacing == GridSpacing::Adaptive;
+                                    is_grid_changed |= ui
+                                        .add_enabled(
+                                            self.is_grid_enabled,
+                                            egui::Checkbox::new(
+                                                &mut is_grid_spacing_adaptive,
+                                                "Adaptive Grid Spacing",
+                                            ),
+                                        )
+                                        .changed();
+                                    is_grid_changed |= ui
+                                        .add_enabled(
+                                            self.is_grid_enabled && !is_grid_spacing_adaptive,
+                                            egui::Slider::new(
+                                                &mut grid_fixed_spacing,
+                                                0.01..=100.0,
+                                            )
+                                            .logarithmic(true)
+                                            .text("Grid Spacing"),
+                                        )
+                                        .changed();
+
+                                    if is_grid_changed {
+                                        self.grid_spacing = if is_grid_spacing_adaptive {
+                                            GridSpacing::Adaptive
+                                        } else {
+                                            GridSpacing::Fixed(grid_fixed_spacing)
+                                        };
+
+                                        update_buffer(
+                                            &self.renderer.wgpu.queue,
+                                            &self.renderer.grid.uniform_buffer,
+                                            &[GridUniform::new(
+                                                &self.camera,
+                                                self.grid_fade_distance,
+                                                self.grid_spacing,
+                                            )],
+                                        );
+                                    }
+
+                                    let mut are_lights_changed = false;
+                                    let mut removed_light_index = None;
+
+                                    for (i, (azi, alt)) in
+                                        self.directional_light_azi_alts.iter_mut().enumerate()
+                                    {
+                                        ui.label(format!("Light {}", i + 1));
+
+                                        are_lights_changed |= ui
+                                            .add(
+                                                egui::Slider::new(azi, 0.0..=360.0).text("Azimuth"),
+                                            )
+                                            .changed();
+
+                                        are_lights_changed |= ui
+                                            .add(
+                                                egui::Slider::new(alt, 0.0..=90.0).text("Altitude"),
+                                            )
+                                            .changed();
+
+                                        if ui.button("Remove Light").clicked() {
+                                            removed_light_index = Some(i);
+                                        }
+                                    }
+
+                                    if let Some(i) = removed_light_index {
+                                        self.directional_light_azi_alts.remove(i);
+                                        are_lights_changed = true;
+                                    }
+
+                                    let can_add_light = self.directional_light_azi_alts.len()
+                                        < lights::MAX_DIRECTIONAL_LIGHTS;
+                                    if ui
+                                        .add_enabled(can_add_light, egui::Button::new("Add Light"))
+                                        .clicked()
+                                    {
+                                        self.directional_light_azi_alts.push((45.0, 45.0));
+                                        are_lights_changed = true;
+                                    }
+
+                                    if are_lights_changed {
+                                        Self::update_directional_light_buffers(
+                                            &self.directional_light_azi_alts,
+                                            &self.renderer,
+                                        );
+
+                                        Self::reset_frame_count(
+                                            &mut self.frame_count,
+                                            &self.renderer.wgpu,
+                                            self.renderer.raytracer.as_ref().ok(),
+                                        );
+                                    }
+
+                                    let mut are_point_lights_changed = false;
+                                    let mut removed_point_light_index = None;
+
+                                    for (i, point_light) in self.point_lights.iter_mut().enumerate()
+                                    {
+                                        ui.label(format!("Point Light {}", i + 1));
+
+                                        ui.horizontal(|ui| {
+                                            for axis in point_light.position.as_mut() {
+                                                are_point_lights_changed |= ui
+                                                    .add(egui::DragValue::new(axis).speed(0.05))
+                                                    .changed();
+                                            }
+                                        });
+
+                                        let mut color = point_light.color.to_array();
+                                        if ui.color_edit_button_rgb(&mut color).changed() {
+                                            point_light.color = color.into();
+                                            are_point_lights_changed = true;
+                                        }
+
+                                        are_point_lights_changed |= ui
+                                            .add(
+                                                egui::Slider::new(
+                                                    &mut point_light.intensity,
+                                                    0.0..=50.0,
+                                                )
+                                                .text("Intensity"),
+                                            )
+                                            .changed();
+
+                                        are_point_lights_changed |= ui
+                                            .add(
+                                                egui::Slider::new(
+                                                    &mut point_light.range,
+                                                    0.1..=20.0,
+                                                )
+                                                .text("Range"),
+                                            )
+                                            .changed();
+
+                                        if ui.button("Remove Point Light").clicked() {
+                                            removed_point_light_index = Some(i);
+                                        }
+                                    }
+
+                                    if let Some(i) = removed_point_light_index {
+                                        self.point_lights.remove(i);
+                                        are_point_lights_changed = true;
+                                    }
+
+                                    let can_add_point_light =
+                                        self.point_lights.len() < lights::MAX_POINT_LIGHTS;
+                                    if ui
+                                        .add_enabled(
+                                            can_add_point_light,
+                                            egui::Button::new("Add Point Light"),
+                                        )
+                                        .clicked()
+                                    {
+                                        self.point_lights.push(lights::PointLight::new(
+                                            Vec3::ZERO,
+                                            Vec3::ONE,
+                                            1.0,
+                                            5.0,
+                                        ));
+                                        are_point_lights_changed = true;
+                                    }
+
+                                    // Only the rasterizer shades with point lights,
+                                    // so the raytracer doesn't need to be reset
+                                    if are_point_lights_changed {
+                                        Self::update_point_light_buffers(
+                                            &self.point_lights,
+                                            &self.renderer,
+                                        );
+                                    }
+
+                                    egui::ComboBox::from_label("Load Primitive")
+                                        .selected_text("Replace Mesh")
+                                        .show_ui(ui, |ui| {
+                                            for primitive in Primitive::ALL {
+                                                if ui
+                                                    .selectable_label(false, primitive.name())
+                                                    .clicked()
+                                                {
+                                                    requested_primitive = Some(primitive);
+                                                }
+                                            }
+                                        });
+
+                                    #[cfg(not(target_arch = "wasm32"))]
+                                    {
+                                        ui.label("Environment Map (.hdr/.exr)");
+                                        ui.text_edit_singleline(&mut self.environment_map_path);
+                                        if ui.button("Load Environment Map").clicked() {
+                                            is_environment_map_load_requested = true;
+                                        }
+
+                                        if let Some(error) = &self.environment_map_error {
+                                            ui.colored_label(egui::Color32::RED, error);
+                                        }
+
+                                        ui.label(
+                                            "Drop an .obj file on the window to replace the mesh",
+                                        );
+                                        if let Some(error) = &self.mesh_error {
+                                            ui.colored_label(egui::Color32::RED, error);
+                                        }
+                                    }
+
+                                    // Run the raytracer when the checkbox is toggled on
+                                    if ui
+                                        .add_enabled(
+                                            self.renderer.raytracer.is_ok(),
+                                            egui::Checkbox::new(
+                                                &mut self.is_raytracer_enabled,
+                                                "Raytracing",
+                                            ),
+                                        )
+                                        .changed()
+                                    {
+                                        Self::reset_frame_count(
+                                            &mut self.frame_count,
+                                            &self.renderer.wgpu,
+                                            self.renderer.raytracer.as_ref().ok(),
+                                        );
+                                    }
+
+                                    if let Err(reason) = &self.renderer.raytracer {
+                                        ui.colored_label(
+                                            egui::Color32::RED,
+                                            format!("Raytracing is unavailable: {reason}"),
+                                        );
+                                    }
+
+                                    if let Ok(raytracer) = &self.renderer.raytracer {
+                                        if ui
+                                            .add(
+                                                egui::Slider::new(
+                                                    &mut self.raytracer_settings.max_bounces,
+                                                    1..=32,
+                                                )
+                                                .text("Max Bounces"),
+                                            )
+                                            .changed()
+                                        {
+                                            update_buffer(
+                                                &self.renderer.wgpu.queue,
+                                                &raytracer.settings_uniform_buffer,
+                                                &[self.raytracer_settings],
+                                            );
+
+                                            Self::reset_frame_count(
+                                                &mut self.frame_count,
+                                                &self.renderer.wgpu,
+                                                Some(raytracer),
+                                            );
+                                        }
+
+                                        // Accumulation stops as soon as the frame count reaches the new maximum
+                                        ui.add(
+                                            egui::Slider::new(
+                                                &mut self.raytracer_max_frames,
+                                                1..=4096,
+                                            )
+                                            .logarithmic(true)
+                                            .text("Max Frames"),
+                                        );
+                                        if raytracer.accumulation.max_frames()
+                                            < self.raytracer_max_frames
+                                        {
+                                            ui.label(format!(
+                                                "{:?} accumulation stops at {} frames",
+                                                raytracer.accumulation,
+                                                raytracer.accumulation.max_frames()
+                                            ));
+                                        }
+
+                                        // Camera movement is applied at most once per target frame time,
+                                        // four times less often while raytracing
+                                        let mut target_frame_time_ms =
+                                            self.target_frame_time * 1000.0;
+                                        if ui
+                                            .add(
+                                                egui::Slider::new(
+                                                    &mut target_frame_time_ms,
+                                                    1.0..=100.0,
+                                                )
+                                                .logarithmic(true)
+                                                .text("Target Frame Time (ms)"),
+                                            )
+                                            .changed()
+                                        {
+                                            self.target_frame_time = target_frame_time_ms / 1000.0;
+                                        }
+
+                                        let mut aperture = self.camera.aperture();
+                                        let mut focus_distance = self.camera.focus_distance();
+                                        let is_aperture_changed = ui
+                                            .add(
+                                                egui::Slider::new(&mut aperture, 0.0..=1.0)
+                                                    .text("Aperture"),
+                                            )
+                                            .changed();
+                                        let is_focus_distance_changed = ui
+                                            .add_enabled(
+                                                aperture > 0.0,
+                                                egui::Slider::new(&mut focus_distance, 0.1..=100.0)
+                                                    .logarithmic(true)
+                                                    .text("Focus Distance"),
+                                            )
+                                            .changed();
+                                        if is_aperture_changed || is_focus_distance_changed {
+                                            self.camera.set_aperture(aperture);
+                                            self.camera.set_focus_distance(focus_distance);
+                                            update_buffer(
+                                                &self.renderer.wgpu.queue,
+                                                &raytracer.camera_lens_uniform_buffer,
+                                                &[CameraLensUniform::new(&self.camera)],
+                                            );
+
+                                            Self::reset_frame_count(
+                                                &mut self.frame_count,
+                                                &self.renderer.wgpu,
+                                                Some(raytracer),
+                                            );
+                                        }
+
+                                        ui.checkbox(&mut self.is_denoiser_enabled, "Denoise");
+
+                                        // Exposure is applied when displaying the result, so the accumulation is kept
+                                        if ui
+                                            .add(
+                                                egui::Slider::new(
+                                                    &mut self.raytracer_exposure,
+                                                    0.1..=10.0,
+                                                )
+                                                .logarithmic(true)
+                                                .text("Exposure"),
+                                            )
+                                            .changed()
+                                        {
+                                            update_buffer(
+                                                &self.renderer.wgpu.queue,
+                                                &raytracer.tonemap_uniform_buffer,
+                                                &[TonemapUniform::new(
+                                                    self.raytracer_exposure,
+                                                    raytracer.accumulation,
+                                                )],
+                                            );
+                                        }
+
+                                        let mut is_f16_accumulation_enabled =
+                                            raytracer.accumulation == RaytracerAccumulation::F16;
+                                        if ui
+                                            .checkbox(
+                                                &mut is_f16_accumulation_enabled,
+                                                "F16 Accumulation",
+                                            )
+                                            .changed()
+                                        {
+                                            requested_raytracer_accumulation =
+                                                Some(if is_f16_accumulation_enabled {
+                                                    RaytracerAccumulation::F16
+                                                } else {
+                                                    RaytracerAccumulation::Compatible
+                                                });
+                                        }
+                                    }
+
+                                    if let Some(error) = &self.raytracer_accumulation_error {
+                                        ui.colored_label(egui::Color32::RED, error);
+                                    }
+                                });
                         });
                 });
+
+        // The egui context borrows the renderer, so the environment map is loaded afterwards
+        #[cfg(not(target_arch = "wasm32"))]
+        if is_environment_map_load_requested {
+            self.load_environment_map();
+        }
+
+        // The raytracer is recreated afterwards for the same reason
+        if let Some(accumulation) = requested_raytracer_accumulation {
+            self.set_raytracer_accumulation(accumulation);
+        }
+
+        if let Some(culling) = requested_rasterizer_culling {
+            self.renderer.set_rasterizer_culling(culling);
+        }
+
+        if let Some(present_mode) = requested_present_mode {
+            self.renderer.set_present_mode(present_mode);
+        }
+
+        if let Some(primitive) = requested_primitive {
+            self.set_mesh(&primitive.mesh());
+        }
+
         let egui_primitives = self
-            .renderer
             .egui
             .state
             .egui_ctx()
@@ -302,7 +973,7 @@ impl Engine {
         };
 
         for (id, image_delta) in egui_full_output.textures_delta.set {
-            self.renderer.egui.renderer.update_texture(
+            self.egui.renderer.update_texture(
                 &self.renderer.wgpu.device,
                 &self.renderer.wgpu.queue,
                 id,
@@ -311,23 +982,63 @@ impl Engine {
         }
 
         {
-            if self.is_raytracer_enabled {
+            let [r, g, b] = self.clear_color.map(f64::from);
+            let clear_color = wgpu::Color { r, g, b, a: 1.0 };
+
+            if let (true, Ok(raytracer)) = (self.is_raytracer_enabled, &self.renderer.raytracer) {
+                let raytracer_render_bind_group = if self.is_denoiser_enabled {
+                    &raytracer.denoiser.render_bind_group
+                } else {
+                    &raytracer.render_bind_group
+                };
+
                 render_raytracer(
                     &mut render_encoder,
                     &surface_texture_view,
-                    &self.renderer.raytracer.render_bind_group,
-                    &self.renderer.raytracer.render_pipeline,
+                    raytracer_render_bind_group,
+                    &raytracer.render_pipeline,
+                    clear_color,
+                    self.renderer.gpu_timer.as_mut().map(|gpu_timer| {
+                        gpu_timer.render_pass_timestamp_writes(GpuPass::RaytracerRender)
+                    }),
+                    &mut self.render_stats,
                 );
             } else {
+                let (mesh_min, mesh_max) = self.renderer.mesh_bounds;
+                let is_mesh_culled = self.is_frustum_culling_enabled
+                    && !Frustum::from_view_projection(self.camera.view_projection())
+                        .intersects_aabb(mesh_min, mesh_max);
+
+                let (index_buffer, num_indices, render_pipeline) = if self.is_wireframe_enabled {
+                    (
+                        &self.renderer.wireframe_index_buffer,
+                        self.renderer.num_wireframe_indices,
+                        &self.renderer.rasterizer.wireframe_render_pipeline,
+                    )
+                } else {
+                    (
+                        &self.renderer.index_buffer,
+                        self.renderer.num_indices,
+                        &self.renderer.rasterizer.render_pipeline,
+                    )
+                };
+
                 render_rasterizer(
                     &mut render_encoder,
                     &surface_texture_view,
                     &self.renderer.rasterizer.depth_texture,
                     &self.renderer.vertex_buffer,
-                    &self.renderer.index_buffer,
-                    self.renderer.num_indices,
+                    index_buffer,
+                    num_indices,
+                    is_mesh_culled,
                     &self.renderer.rasterizer.bind_group,
-                    &self.renderer.rasterizer.render_pipeline,
+                    render_pipeline,
+                    self.is_grid_enabled.then_some(&self.renderer.grid),
+                    clear_color,
+                    self.renderer.gpu_timer.as_mut().map(|gpu_timer| {
+                        gpu_timer.render_pass_timestamp_writes(GpuPass::Rasterizer)
+                    }),
+                    &mut self.render_stats,
                 );
             };
 
@@ -336,10 +1047,27 @@ impl Engine {
                 &self.renderer.wgpu.queue,
                 &mut render_encoder,
                 &surface_texture_view,
-                &mut self.renderer.egui.renderer,
+                &mut self.egui.renderer,
                 &egui_primitives,
                 &egui_screen_descriptor,
+                self.renderer
+                    .gpu_timer
+                    .as_mut()
+                    .map(|gpu_timer| gpu_timer.render_pass_timestamp_writes(GpuPass::Egui)),
             );
+
+            if let Some(gpu_timer) = &mut self.renderer.gpu_timer {
+                gpu_timer.resolve(&mut render_encoder);
+            }
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if std::mem::take(&mut self.is_frame_capture_requested) {
+            self.frame_capture = Some(FrameCapture::new(
+                &self.renderer.wgpu.device,
+                &mut render_encoder,
+                &surface_texture.texture,
+            ));
         }
 
         self.renderer
@@ -348,14 +1076,197 @@ impl Engine {
             .submit(Some(render_encoder.finish()));
         surface_texture.present();
 
+        if let Some(gpu_timer) = &mut self.renderer.gpu_timer {
+            gpu_timer.map_readback();
+        }
+
+        self.last_render_stats = self.render_stats;
+
         for id in egui_full_output.textures_delta.free {
-            self.renderer.egui.renderer.free_texture(&id);
+            self.egui.renderer.free_texture(&id);
         }
 
         Ok(())
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load_environment_map(&mut self) {
+        let path = std::path::Path::new(&self.environment_map_path);
+        match crate::environment::EnvironmentMap::load(path)
+            .map_err(|error| error.to_string())
+            .and_then(|environment_map| self.renderer.set_environment_map(&environment_map))
+        {
+            Ok(()) => {
+                self.environment_map_error = None;
+
+                Self::reset_frame_count(
+                    &mut self.frame_count,
+                    &self.renderer.wgpu,
+                    self.renderer.raytracer.as_ref().ok(),
+                );
+            }
+            Err(error) => {
+                log::error!(
+                    "Failed to load environment map {}: {}",
+                    path.display(),
+                    error
+                );
+                self.environment_map_error = Some(error);
+            }
+        }
+    }
+
+    fn set_raytracer_accumulation(&mut self, accumulation: RaytracerAccumulation) {
+        match self.renderer.set_raytracer_accumulation(
+            accumulation,
+            &self.camera,
+            &self.raytracer_settings,
+            self.raytracer_exposure,
+        ) {
+            Ok(()) => {
+                self.raytracer_accumulation_error = None;
+
+                Self::reset_frame_count(
+                    &mut self.frame_count,
+                    &self.renderer.wgpu,
+                    self.renderer.raytracer.as_ref().ok(),
+                );
+            }
+            Err(error) => {
+                log::error!(
+                    "Failed to switch to {:?} accumulation: {}",
+                    accumulation,
+                    error
+                );
+                self.raytracer_accumulation_error = Some(error);
+            }
+        }
+    }
+
+    /// Renders a frame and returns what was presented, including the UI.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn capture_frame(&mut self) -> Result<image::RgbaImage, CaptureError> {
+        if !self
+            .renderer
+            .wgpu
+            .surface_config
+            .usage
+            .contains(wgpu::TextureUsages::COPY_SRC)
+        {
+            return Err(CaptureError::Unsupported);
+        }
+
+        self.is_frame_capture_requested = true;
+        let render_result = self.render();
+        // Don't capture a later frame if this one failed
+        self.is_frame_capture_requested = false;
+        render_result.map_err(CaptureError::Surface)?;
+
+        self.frame_capture
+            .take()
+            .expect("The rendered frame should have been captured")
+            .read(&self.renderer.wgpu.device)
+    }
+
+    /// Saves the next frame to a timestamped PNG in the working directory.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_screenshot(&mut self) {
+        let seconds_since_epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = format!("screenshot-{seconds_since_epoch}.png");
+
+        match self
+            .capture_frame()
+            .and_then(|image| image.save(&path).map_err(CaptureError::Image))
+        {
+            Ok(()) => log::info!("Saved screenshot to {path}"),
+            Err(error) => log::error!("Failed to save screenshot: {error}"),
+        }
+    }
+
+    /// Replaces the mesh with one loaded from a dropped file and frames it.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_mesh(&mut self, path: &std::path::Path) {
+        let mesh = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("obj") => {
+                Mesh::from_obj(&path.to_string_lossy()).map_err(|error| error.to_string())
+            }
+            _ => Err("Only .obj files can be dropped".to_string()),
+        };
+
+        match mesh {
+            Ok(mesh) => self.set_mesh(&mesh),
+            Err(error) => {
+                log::error!("Failed to load mesh {}: {}", path.display(), error);
+                self.mesh_error = Some(error);
+            }
+        }
+    }
+
+    /// Replaces the mesh both renderers draw and frames it.
+    pub fn set_mesh(&mut self, mesh: &Mesh) {
+        self.renderer.set_mesh(mesh);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.mesh_error = None;
+        }
+
+        // Also resets the raytracer, which was tracing the old mesh
+        self.frame_mesh();
+    }
+
+    // Switches between the rasterizer and the raytracer, like the Raytracing checkbox
+    pub fn toggle_raytracer(&mut self) {
+        if self.renderer.raytracer.is_err() {
+            return;
+        }
+
+        self.is_raytracer_enabled = !self.is_raytracer_enabled;
+        Self::reset_frame_count(
+            &mut self.frame_count,
+            &self.renderer.wgpu,
+            self.renderer.raytracer.as_ref().ok(),
+        );
+
+        self.window.request_redraw();
+    }
+
+    // Fits the camera's view to the mesh
+    pub fn frame_mesh(&mut self) {
+        let (mesh_min, mesh_max) = self.renderer.mesh_bounds;
+        self.camera.frame_bounds(mesh_min, mesh_max, 0.1);
+        self.update_camera_uniforms();
+
+        // Otherwise the next orbit would snap the camera back around the old focus
+        if let CameraControllerMode::Orbit { .. } = self.camera_controller.mode() {
+            self.camera_controller
+                .set_orbit_mode((mesh_min + mesh_max) / 2.0, &self.camera);
+        }
+
+        Self::reset_frame_count(
+            &mut self.frame_count,
+            &self.renderer.wgpu,
+            self.renderer.raytracer.as_ref().ok(),
+        );
+
+        self.window.request_redraw();
+    }
+
     pub fn input(&mut self, event: &WindowEvent) {
+        // Clicks on the UI shouldn't also orbit or pan the camera.
+        // Releases still go through so a drag that ends over the UI stops.
+        if let WindowEvent::MouseInput {
+            state: ElementState::Pressed,
+            ..
+        } = event
+        {
+            if self.egui.state.egui_ctx().is_pointer_over_area() {
+                return;
+            }
+        }
+
         self.camera_controller.process_events(event);
     }
 
@@ -363,14 +1274,18 @@ impl Engine {
         self.time_since_last_frame += self.delta_time;
 
         // Raytracing is expensive, so run it every 4 frames
-        if self.is_raytracer_enabled && self.time_since_last_frame < self.target_frame_time * 4.0 {
-            return;
-        } else if self.time_since_last_frame < self.target_frame_time {
-            return;
+        let min_frame_time = if self.is_raytracer_enabled {
+            self.target_frame_time * 4.0
         } else {
-            self.time_since_last_frame = 0.0;
+            self.target_frame_time
+        };
+
+        if self.time_since_last_frame < min_frame_time {
+            return;
         }
 
+        self.time_since_last_frame = 0.0;
+
         self.camera_controller
             .update_camera(&mut self.camera, self.delta_time);
         self.update_camera_uniforms();
@@ -378,7 +1293,7 @@ impl Engine {
         Self::reset_frame_count(
             &mut self.frame_count,
             &self.renderer.wgpu,
-            &self.renderer.raytracer,
+            self.renderer.raytracer.as_ref().ok(),
         );
 
         self.window.request_redraw();
@@ -393,17 +1308,74 @@ impl Engine {
 
         update_buffer(
             &self.renderer.wgpu.queue,
-            &self.renderer.raytracer.camera_to_world_uniform_buffer,
-            &[self.camera.camera_to_world().to_cols_array_2d()],
+            &self.renderer.grid.uniform_buffer,
+            &[GridUniform::new(
+                &self.camera,
+                self.grid_fade_distance,
+                self.grid_spacing,
+            )],
+        );
+
+        if let Ok(raytracer) = &self.renderer.raytracer {
+            update_buffer(
+                &self.renderer.wgpu.queue,
+                &raytracer.camera_to_world_uniform_buffer,
+                &[self.camera.camera_to_world().to_cols_array_2d()],
+            );
+
+            update_buffer(
+                &self.renderer.wgpu.queue,
+                &raytracer.camera_inverse_projection_uniform_buffer,
+                &[self.camera.camera_inverse_projection().to_cols_array_2d()],
+            );
+
+            update_buffer(
+                &self.renderer.wgpu.queue,
+                &raytracer.camera_lens_uniform_buffer,
+                &[CameraLensUniform::new(&self.camera)],
+            );
+        }
+    }
+
+    fn directional_lights(
+        directional_light_azi_alts: &[(f32, f32)],
+    ) -> Vec<lights::DirectionalLight> {
+        directional_light_azi_alts
+            .iter()
+            .map(|&(azi, alt)| lights::DirectionalLight::from_azi_alt(azi, alt))
+            .collect()
+    }
+
+    fn update_directional_light_buffers(
+        directional_light_azi_alts: &[(f32, f32)],
+        renderer: &Renderer,
+    ) {
+        let directional_lights = Self::directional_lights(directional_light_azi_alts);
+
+        update_buffer(
+            &renderer.wgpu.queue,
+            &renderer.directional_lights_buffer,
+            &lights::directional_lights_data(&directional_lights),
         );
 
         update_buffer(
-            &self.renderer.wgpu.queue,
-            &self
-                .renderer
-                .raytracer
-                .camera_inverse_projection_uniform_buffer,
-            &[self.camera.camera_inverse_projection().to_cols_array_2d()],
+            &renderer.wgpu.queue,
+            &renderer.directional_light_count_uniform_buffer,
+            &[lights::directional_light_count(&directional_lights)],
+        );
+    }
+
+    fn update_point_light_buffers(point_lights: &[lights::PointLight], renderer: &Renderer) {
+        update_buffer(
+            &renderer.wgpu.queue,
+            &renderer.point_lights_buffer,
+            &lights::point_lights_data(point_lights),
+        );
+
+        update_buffer(
+            &renderer.wgpu.queue,
+            &renderer.point_light_count_uniform_buffer,
+            &[lights::point_light_count(point_lights)],
         );
     }
 
@@ -424,14 +1396,16 @@ impl Engine {
     fn reset_frame_count(
         frame_count: &mut u32,
         wgpu: &crate::wgpu::RendererWgpuResources,
-        raytracer: &crate::raytracer::Raytracer,
+        raytracer: Option<&crate::raytracer::Raytracer>,
     ) {
         *frame_count = 0;
 
-        update_buffer(
-            &wgpu.queue,
-            &raytracer.frame_count_uniform_buffer,
-            &[*frame_count],
-        );
+        if let Some(raytracer) = raytracer {
+            update_buffer(
+                &wgpu.queue,
+                &raytracer.frame_count_uniform_buffer,
+                &[*frame_count],
+            );
+        }
     }
 }
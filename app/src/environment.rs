@@ -0,0 +1,82 @@
+/// An equirectangular environment map stored as linear RGBA texels, row by row.
+pub struct EnvironmentMap {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<f32>,
+}
+
+impl EnvironmentMap {
+    // A single black texel, used when no environment map is loaded so the lighting is unchanged.
+    pub fn black() -> Self {
+        Self {
+            width: 1,
+            height: 1,
+            texels: vec![0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    // Radiance `.hdr` and OpenEXR `.exr` files are decoded based on their extension.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &std::path::Path) -> Result<Self, image::ImageError> {
+        Ok(Self::from_image(image::open(path)?))
+    }
+
+    // Like `load`, but the format is guessed from the file's contents.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_memory(bytes: &[u8]) -> Result<Self, image::ImageError> {
+        Ok(Self::from_image(image::load_from_memory(bytes)?))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_image(image: image::DynamicImage) -> Self {
+        let image = image.into_rgba32f();
+        let (width, height) = image.dimensions();
+
+        Self {
+            width,
+            height,
+            texels: image.into_raw(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EnvironmentMap;
+    use crate::test_utils;
+
+    // A 3x3 white map with a (0.5, 0.25, 0.125) texel in the middle
+    const ENVIRONMENT_HDR: &[u8] = include_bytes!("../test_data/environment.hdr");
+
+    #[test]
+    fn hdr_is_decoded_to_linear_rgba() {
+        let environment_map = EnvironmentMap::from_memory(ENVIRONMENT_HDR).unwrap();
+
+        assert_eq!((environment_map.width, environment_map.height), (3, 3));
+        assert_eq!(environment_map.texels.len(), 3 * 3 * 4);
+        let center = (environment_map.width + 1) as usize * 4;
+        assert_eq!(
+            environment_map.texels[center..center + 4],
+            [0.5, 0.25, 0.125, 1.0]
+        );
+        assert_eq!(environment_map.texels[..4], [1.0; 4]);
+    }
+
+    #[test]
+    fn hdr_is_uploaded_to_a_float_texture() {
+        let Some((_, device, queue)) = test_utils::device(wgpu::Features::empty()) else {
+            return;
+        };
+        let environment_map = EnvironmentMap::from_memory(ENVIRONMENT_HDR).unwrap();
+
+        let texture = crate::wgpu::Texture::create_environment_texture(
+            &device,
+            &queue,
+            &environment_map,
+            "test_environment_texture",
+        );
+
+        assert_eq!(texture.texture.format(), wgpu::TextureFormat::Rgba32Float);
+        assert_eq!((texture.texture.width(), texture.texture.height()), (3, 3));
+    }
+}
@@ -0,0 +1,106 @@
+use glam::{Mat4, Vec3, Vec4};
+
+/// The six planes bounding the volume a camera can see, pointing inwards.
+/// Each plane is stored as `(normal, distance)` so a point `p` is inside when `normal.dot(p) + distance >= 0`.
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix, with the near plane where wgpu clips at a clip space depth of 0.
+    /// The camera's projection maps its near plane to -1 like OpenGL does, so this plane is a little further away,
+    /// but nothing in front of it would be drawn anyway.
+    // Ref: https://www.gamedevs.org/uploads/fast-extraction-viewing-frustum-planes-from-world-view-projection-matrix.pdf
+    pub fn from_view_projection(view_projection: Mat4) -> Self {
+        let row_0 = view_projection.row(0);
+        let row_1 = view_projection.row(1);
+        let row_2 = view_projection.row(2);
+        let row_3 = view_projection.row(3);
+
+        let planes = [
+            row_3 + row_0, // Left
+            row_3 - row_0, // Right
+            row_3 + row_1, // Bottom
+            row_3 - row_1, // Top
+            row_2,         // Near
+            row_3 - row_2, // Far
+        ]
+        .map(|plane| plane / plane.truncate().length());
+
+        Self { planes }
+    }
+
+    /// Returns false only if the box is entirely outside one of the planes.
+    /// Boxes near the corners of the frustum can be reported as intersecting when they aren't.
+    pub fn intersects_aabb(&self, min: Vec3, max: Vec3) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner of the box furthest along the plane's normal
+            let positive_corner = Vec3::select(plane.truncate().cmpge(Vec3::ZERO), max, min);
+            plane.truncate().dot(positive_corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_2};
+
+    use super::*;
+    use crate::camera::Camera;
+
+    // Looks down -Z from the origin with a 90 degree field of view, so the side planes are at 45 degrees
+    fn frustum() -> Frustum {
+        let camera = Camera::new(Vec3::ZERO, Vec3::NEG_Z, 1.0, FRAC_PI_2, 1.0, 10.0);
+        Frustum::from_view_projection(camera.view_projection())
+    }
+
+    fn intersects_cube(frustum: &Frustum, center: Vec3, half_size: f32) -> bool {
+        frustum.intersects_aabb(center - half_size, center + half_size)
+    }
+
+    #[test]
+    fn planes_are_normalized_and_point_inwards() {
+        let planes = frustum().planes;
+        let expected_planes = [
+            Vec4::new(FRAC_1_SQRT_2, 0.0, -FRAC_1_SQRT_2, 0.0),
+            Vec4::new(-FRAC_1_SQRT_2, 0.0, -FRAC_1_SQRT_2, 0.0),
+            Vec4::new(0.0, FRAC_1_SQRT_2, -FRAC_1_SQRT_2, 0.0),
+            Vec4::new(0.0, -FRAC_1_SQRT_2, -FRAC_1_SQRT_2, 0.0),
+            // Where the OpenGL style depth of -1 to 1 crosses 0, 2 * near * far / (near + far)
+            Vec4::new(0.0, 0.0, -1.0, -20.0 / 11.0),
+            Vec4::new(0.0, 0.0, 1.0, 10.0),
+        ];
+
+        for (plane, expected_plane) in planes.iter().zip(expected_planes) {
+            assert!(
+                plane.abs_diff_eq(expected_plane, 1e-5),
+                "{plane} != {expected_plane}"
+            );
+        }
+    }
+
+    #[test]
+    fn boxes_inside_or_straddling_the_frustum_intersect() {
+        let frustum = frustum();
+
+        assert!(intersects_cube(&frustum, Vec3::new(0.0, 0.0, -5.0), 0.5));
+        // Straddling the right plane at x = 5
+        assert!(intersects_cube(&frustum, Vec3::new(5.0, 0.0, -5.0), 0.5));
+        // Straddling the far plane
+        assert!(intersects_cube(&frustum, Vec3::new(0.0, 0.0, -10.0), 0.5));
+        // Containing the whole frustum
+        assert!(intersects_cube(&frustum, Vec3::ZERO, 100.0));
+    }
+
+    #[test]
+    fn boxes_outside_a_plane_are_culled() {
+        let frustum = frustum();
+
+        assert!(!intersects_cube(&frustum, Vec3::new(0.0, 0.0, 5.0), 0.5));
+        assert!(!intersects_cube(&frustum, Vec3::new(0.0, 0.0, -20.0), 0.5));
+        assert!(!intersects_cube(&frustum, Vec3::new(-8.0, 0.0, -5.0), 0.5));
+        assert!(!intersects_cube(&frustum, Vec3::new(0.0, 8.0, -5.0), 0.5));
+        // Past the camera's near plane, but in front of where wgpu clips
+        assert!(!intersects_cube(&frustum, Vec3::new(0.0, 0.0, -1.4), 0.1));
+    }
+}
@@ -0,0 +1,297 @@
+use std::sync::{Arc, Mutex};
+
+/// The passes the GPU timer can measure, each gets a pair of timestamp queries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpuPass {
+    Raytracer,
+    Denoiser,
+    RaytracerRender,
+    Rasterizer,
+    Egui,
+}
+
+impl GpuPass {
+    pub const ALL: [Self; 5] = [
+        Self::Raytracer,
+        Self::Denoiser,
+        Self::RaytracerRender,
+        Self::Rasterizer,
+        Self::Egui,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Raytracer => "Raytracer",
+            Self::Denoiser => "Denoiser",
+            Self::RaytracerRender => "Raytracer Render",
+            Self::Rasterizer => "Rasterizer",
+            Self::Egui => "Egui",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn beginning_query_index(self) -> u32 {
+        self.index() as u32 * 2
+    }
+
+    fn end_query_index(self) -> u32 {
+        self.index() as u32 * 2 + 1
+    }
+}
+
+const QUERY_COUNT: u32 = GpuPass::ALL.len() as u32 * 2;
+const PASS_TIMESTAMPS_SIZE: u64 = 2 * wgpu::QUERY_SIZE as u64;
+// Each pass resolves separately, and resolves have to start at an aligned offset
+const RESOLVE_BUFFER_SIZE: u64 = GpuPass::ALL.len() as u64 * wgpu::QUERY_RESOLVE_BUFFER_ALIGNMENT;
+const READBACK_BUFFER_SIZE: u64 = GpuPass::ALL.len() as u64 * PASS_TIMESTAMPS_SIZE;
+
+// Which passes wrote their timestamps, indexed by `GpuPass`
+type PassMask = [bool; GpuPass::ALL.len()];
+
+enum ReadbackState {
+    Idle,
+    // Copied into the readback buffer by the last recorded frame, not yet submitted
+    Copied(PassMask),
+    // Waiting for the readback buffer to be mapped
+    Mapping(
+        PassMask,
+        Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>,
+    ),
+}
+
+/// Measures how long the GPU spends on each pass with timestamp queries.
+/// The timings are read back asynchronously, so they lag a few frames behind.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Nanoseconds per timestamp tick
+    timestamp_period: f32,
+    written_passes: PassMask,
+    readback_state: ReadbackState,
+    pass_times: [Option<f32>; GpuPass::ALL.len()],
+}
+
+impl GpuTimer {
+    /// Returns `None` if the device wasn't created with `Features::TIMESTAMP_QUERY`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GPU Timer Query Set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: QUERY_COUNT,
+        });
+
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Timer Resolve Buffer"),
+            size: RESOLVE_BUFFER_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Timer Readback Buffer"),
+            size: READBACK_BUFFER_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            timestamp_period: queue.get_timestamp_period(),
+            written_passes: [false; GpuPass::ALL.len()],
+            readback_state: ReadbackState::Idle,
+            pass_times: [None; GpuPass::ALL.len()],
+        })
+    }
+
+    pub fn compute_pass_timestamp_writes(
+        &mut self,
+        pass: GpuPass,
+    ) -> wgpu::ComputePassTimestampWrites<'_> {
+        self.written_passes[pass.index()] = true;
+
+        wgpu::ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(pass.beginning_query_index()),
+            end_of_pass_write_index: Some(pass.end_query_index()),
+        }
+    }
+
+    pub fn render_pass_timestamp_writes(
+        &mut self,
+        pass: GpuPass,
+    ) -> wgpu::RenderPassTimestampWrites<'_> {
+        self.written_passes[pass.index()] = true;
+
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(pass.beginning_query_index()),
+            end_of_pass_write_index: Some(pass.end_query_index()),
+        }
+    }
+
+    /// Copies this frame's timestamps into the readback buffer, should be recorded after the last timed pass.
+    /// Skipped while the previous frame's timestamps are still being read back.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let written_passes =
+            std::mem::replace(&mut self.written_passes, [false; GpuPass::ALL.len()]);
+
+        if !matches!(self.readback_state, ReadbackState::Idle) {
+            return;
+        }
+
+        // Queries that weren't written this frame would hold stale or undefined timestamps
+        for pass in GpuPass::ALL {
+            if !written_passes[pass.index()] {
+                continue;
+            }
+
+            let resolve_offset = pass.index() as u64 * wgpu::QUERY_RESOLVE_BUFFER_ALIGNMENT;
+            encoder.resolve_query_set(
+                &self.query_set,
+                pass.beginning_query_index()..pass.end_query_index() + 1,
+                &self.resolve_buffer,
+                resolve_offset,
+            );
+            encoder.copy_buffer_to_buffer(
+                &self.resolve_buffer,
+                resolve_offset,
+                &self.readback_buffer,
+                pass.index() as u64 * PASS_TIMESTAMPS_SIZE,
+                PASS_TIMESTAMPS_SIZE,
+            );
+        }
+        self.readback_state = ReadbackState::Copied(written_passes);
+    }
+
+    /// Starts reading back the timestamps, should be called after the resolving frame is submitted.
+    pub fn map_readback(&mut self) {
+        let ReadbackState::Copied(written_passes) = self.readback_state else {
+            return;
+        };
+
+        let map_result = Arc::new(Mutex::new(None));
+        let callback_map_result = map_result.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *callback_map_result.lock().unwrap() = Some(result);
+            });
+        self.readback_state = ReadbackState::Mapping(written_passes, map_result);
+    }
+
+    /// Updates the pass times once the readback buffer is mapped.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Poll);
+
+        let ReadbackState::Mapping(written_passes, map_result) = &self.readback_state else {
+            return;
+        };
+        let Some(map_result) = map_result.lock().unwrap().take() else {
+            return;
+        };
+        let written_passes = *written_passes;
+        self.readback_state = ReadbackState::Idle;
+
+        if let Err(error) = map_result {
+            log::warn!("Failed to read back GPU timestamps: {error}");
+            return;
+        }
+
+        {
+            let timestamps_view = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&timestamps_view);
+            for pass in GpuPass::ALL {
+                self.pass_times[pass.index()] = written_passes[pass.index()].then(|| {
+                    let beginning = timestamps[pass.beginning_query_index() as usize];
+                    let end = timestamps[pass.end_query_index() as usize];
+                    end.wrapping_sub(beginning) as f32 * self.timestamp_period / 1_000_000.0
+                });
+            }
+        }
+
+        self.readback_buffer.unmap();
+    }
+
+    /// The milliseconds each pass took the last time its timings were read back,
+    /// skipping passes that didn't run that frame.
+    pub fn pass_times(&self) -> impl Iterator<Item = (GpuPass, f32)> + '_ {
+        GpuPass::ALL
+            .into_iter()
+            .filter_map(|pass| Some((pass, self.pass_times[pass.index()]?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn timer_needs_timestamp_queries() {
+        let Some((_, device, queue)) = test_utils::device(wgpu::Features::empty()) else {
+            return;
+        };
+
+        assert!(GpuTimer::new(&device, &queue).is_none());
+    }
+
+    #[test]
+    fn timed_pass_is_read_back() {
+        let Some((_, device, queue)) = test_utils::device(wgpu::Features::TIMESTAMP_QUERY) else {
+            return;
+        };
+        let mut gpu_timer = GpuTimer::new(&device, &queue).unwrap();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Test Texture"),
+            size: wgpu::Extent3d {
+                width: 4,
+                height: 4,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Test Command Encoder"),
+        });
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Test Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations::default(),
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: Some(gpu_timer.render_pass_timestamp_writes(GpuPass::Rasterizer)),
+            occlusion_query_set: None,
+        });
+        gpu_timer.resolve(&mut encoder);
+        queue.submit(Some(encoder.finish()));
+        gpu_timer.map_readback();
+
+        device.poll(wgpu::Maintain::Wait);
+        gpu_timer.poll(&device);
+
+        let pass_times = gpu_timer.pass_times().collect::<Vec<_>>();
+        assert_eq!(pass_times.len(), 1);
+        assert_eq!(pass_times[0].0, GpuPass::Rasterizer);
+        assert!(pass_times[0].1 >= 0.0);
+        assert!(matches!(gpu_timer.readback_state, ReadbackState::Idle));
+    }
+}
@@ -0,0 +1,222 @@
+use wgpu::util::DeviceExt;
+
+use crate::camera::Camera;
+
+// Matches the `Grid` struct in the grid shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GridUniform {
+    view_proj: [[f32; 4]; 4],
+    inverse_view_proj: [[f32; 4]; 4],
+    camera_position: [f32; 3],
+    fade_distance: f32,
+    minor_spacing: f32,
+    major_spacing: f32,
+    _padding: [f32; 2],
+}
+
+impl GridUniform {
+    pub fn new(camera: &Camera, fade_distance: f32, spacing: GridSpacing) -> Self {
+        let (minor_spacing, major_spacing) = spacing.line_spacing(camera.eye().y);
+
+        Self {
+            view_proj: camera.view_projection().to_cols_array_2d(),
+            inverse_view_proj: camera.view_projection().inverse().to_cols_array_2d(),
+            camera_position: camera.eye().to_array(),
+            fade_distance,
+            minor_spacing,
+            major_spacing,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// How far apart the grid lines are.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GridSpacing {
+    /// Follows the camera height, see `grid_spacing`
+    #[default]
+    Adaptive,
+    /// The minor line spacing in world units, major lines are ten times further apart
+    Fixed(f32),
+}
+
+impl GridSpacing {
+    /// Returns the minor and major grid line spacing for a camera at the given height above the ground.
+    pub fn line_spacing(self, camera_height: f32) -> (f32, f32) {
+        match self {
+            Self::Adaptive => grid_spacing(camera_height),
+            Self::Fixed(minor_spacing) => (minor_spacing, minor_spacing * 10.0),
+        }
+    }
+}
+
+/// Returns the minor and major grid line spacing for a camera at the given height above the ground.
+/// The spacing steps in powers of ten so the lines stay roughly the same size on screen,
+/// with the major lines ten times further apart than the minor ones.
+pub fn grid_spacing(camera_height: f32) -> (f32, f32) {
+    let camera_height = camera_height.abs().max(0.01);
+    let minor_spacing = 10.0_f32.powf(camera_height.log10().floor() - 1.0);
+
+    (minor_spacing, minor_spacing * 10.0)
+}
+
+pub fn initialize_grid(
+    camera: &Camera,
+    fade_distance: f32,
+    spacing: GridSpacing,
+    device: &wgpu::Device,
+    surface_format: wgpu::TextureFormat,
+) -> (wgpu::Buffer, wgpu::BindGroup, wgpu::RenderPipeline) {
+    let grid_shader_source = crate::wgpu::with_color_encoding(
+        include_str!("shaders/rasterizer/grid.wgsl"),
+        surface_format,
+    );
+    let grid_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Grid Shader"),
+        source: wgpu::ShaderSource::Wgsl(grid_shader_source.into()),
+    });
+
+    let grid_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Grid Uniform Buffer"),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        contents: bytemuck::cast_slice(&[GridUniform::new(camera, fade_distance, spacing)]),
+    });
+
+    let grid_bind_group_layout =
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Grid Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+    let grid_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Grid Bind Group"),
+        layout: &grid_bind_group_layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: grid_uniform_buffer.as_entire_binding(),
+        }],
+    });
+
+    let grid_render_pipeline_layout =
+        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid Render Pipeline Layout"),
+            bind_group_layouts: &[&grid_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+    let grid_render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Grid Render Pipeline"),
+        layout: Some(&grid_render_pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &grid_shader,
+            entry_point: "vs_main",
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &grid_shader,
+            entry_point: "fs_main",
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: surface_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // The grid is depth tested against the scene but doesn't occlude anything itself
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: crate::wgpu::Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    });
+
+    (grid_uniform_buffer, grid_bind_group, grid_render_pipeline)
+}
+
+pub struct Grid {
+    pub uniform_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub render_pipeline: wgpu::RenderPipeline,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    fn assert_spacing(camera_height: f32, expected_minor_spacing: f32) {
+        let (minor_spacing, major_spacing) = grid_spacing(camera_height);
+
+        assert!(
+            (minor_spacing - expected_minor_spacing).abs() <= expected_minor_spacing * 1e-5,
+            "{minor_spacing} != {expected_minor_spacing} at a height of {camera_height}"
+        );
+        assert!((major_spacing - minor_spacing * 10.0).abs() <= major_spacing * 1e-5);
+    }
+
+    #[test]
+    fn grid_spacing_steps_in_powers_of_ten() {
+        assert_spacing(1.0, 0.1);
+        assert_spacing(9.9, 0.1);
+        assert_spacing(10.0, 1.0);
+        assert_spacing(250.0, 10.0);
+        assert_spacing(0.5, 0.01);
+    }
+
+    #[test]
+    fn grid_spacing_ignores_which_side_of_the_ground_the_camera_is() {
+        assert_eq!(grid_spacing(-25.0), grid_spacing(25.0));
+        // Right on the ground the spacing bottoms out instead of shrinking forever
+        assert_spacing(0.0, 0.001);
+    }
+
+    #[test]
+    fn fixed_spacing_ignores_the_camera_height() {
+        for camera_height in [0.0, 3.0, 300.0] {
+            assert_eq!(
+                GridSpacing::Fixed(0.5).line_spacing(camera_height),
+                (0.5, 5.0)
+            );
+            assert_eq!(
+                GridSpacing::Adaptive.line_spacing(camera_height),
+                grid_spacing(camera_height)
+            );
+        }
+    }
+
+    #[test]
+    fn grid_pipeline_builds() {
+        let Some((_, device, _)) = test_utils::device(wgpu::Features::empty()) else {
+            return;
+        };
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        initialize_grid(
+            &test_utils::camera(16, 16),
+            20.0,
+            GridSpacing::Fixed(1.0),
+            &device,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+        );
+        let error = pollster::block_on(device.pop_error_scope());
+
+        assert!(error.is_none(), "{error:?}");
+    }
+}
@@ -1,10 +1,19 @@
 pub mod camera;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod capture;
+pub mod denoiser;
 pub mod egui;
+pub mod environment;
+pub mod frustum;
+pub mod gpu_timer;
+pub mod grid;
 pub mod lights;
 pub mod mesh;
 pub mod rasterizer;
 pub mod raytracer;
 pub mod renderer;
+#[cfg(test)]
+mod test_utils;
 pub mod utils;
 pub mod wgpu;
 pub mod application;
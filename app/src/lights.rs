@@ -1,4 +1,15 @@
-use glam::Vec3A;
+use std::mem::{offset_of, size_of};
+
+use glam::{Vec3, Vec3A};
+use wgpu::util::DeviceExt;
+
+/// The maximum number of directional lights the renderers can shade with.
+/// The light storage buffer is allocated for this many lights up front, so lights
+/// beyond the cap are ignored.
+pub const MAX_DIRECTIONAL_LIGHTS: usize = 16;
+
+/// The maximum number of point lights the rasterizer can shade with.
+pub const MAX_POINT_LIGHTS: usize = 16;
 
 pub struct DirectionalLight {
     pub direction: Vec3A,
@@ -21,3 +32,202 @@ impl DirectionalLight {
         }
     }
 }
+
+// Each light direction is padded to a vec4 to match the storage buffer's array stride.
+pub fn directional_lights_data(lights: &[DirectionalLight]) -> [[f32; 4]; MAX_DIRECTIONAL_LIGHTS] {
+    let mut data = [[0.0; 4]; MAX_DIRECTIONAL_LIGHTS];
+    for (light_data, light) in data.iter_mut().zip(lights) {
+        *light_data = light.direction.extend(0.0).to_array();
+    }
+
+    data
+}
+
+pub fn directional_light_count(lights: &[DirectionalLight]) -> u32 {
+    lights.len().min(MAX_DIRECTIONAL_LIGHTS) as u32
+}
+
+pub fn create_directional_light_buffers(
+    device: &wgpu::Device,
+    lights: &[DirectionalLight],
+) -> (wgpu::Buffer, wgpu::Buffer) {
+    let directional_lights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Directional Lights Storage Buffer"),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        contents: bytemuck::cast_slice(&directional_lights_data(lights)),
+    });
+
+    let directional_light_count_uniform_buffer =
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Directional Light Count Uniform Buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            contents: bytemuck::cast_slice(&[directional_light_count(lights)]),
+        });
+
+    (
+        directional_lights_buffer,
+        directional_light_count_uniform_buffer,
+    )
+}
+
+pub struct PointLight {
+    pub position: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    // The distance at which the light's contribution falls off to zero
+    pub range: f32,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, color: Vec3, intensity: f32, range: f32) -> Self {
+        Self {
+            position,
+            color,
+            intensity,
+            range,
+        }
+    }
+}
+
+// Matches the std430 layout of the `PointLight` struct in the shaders,
+// the scalars fill the padding after each vec3.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLightData {
+    position: [f32; 3],
+    intensity: f32,
+    color: [f32; 3],
+    range: f32,
+}
+
+// The vec3s start on 16 byte boundaries and the array stride is 32 bytes, like in WGSL
+const _: () = assert!(size_of::<PointLightData>() == 32);
+const _: () = assert!(offset_of!(PointLightData, color) == 16);
+
+impl From<&PointLight> for PointLightData {
+    fn from(light: &PointLight) -> Self {
+        Self {
+            position: light.position.to_array(),
+            intensity: light.intensity,
+            color: light.color.to_array(),
+            range: light.range,
+        }
+    }
+}
+
+pub fn point_lights_data(lights: &[PointLight]) -> [PointLightData; MAX_POINT_LIGHTS] {
+    let mut data = [PointLightData::default(); MAX_POINT_LIGHTS];
+    for (light_data, light) in data.iter_mut().zip(lights) {
+        *light_data = light.into();
+    }
+
+    data
+}
+
+pub fn point_light_count(lights: &[PointLight]) -> u32 {
+    lights.len().min(MAX_POINT_LIGHTS) as u32
+}
+
+pub fn create_point_light_buffers(
+    device: &wgpu::Device,
+    lights: &[PointLight],
+) -> (wgpu::Buffer, wgpu::Buffer) {
+    let point_lights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Point Lights Storage Buffer"),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        contents: bytemuck::cast_slice(&point_lights_data(lights)),
+    });
+
+    let point_light_count_uniform_buffer =
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Point Light Count Uniform Buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            contents: bytemuck::cast_slice(&[point_light_count(lights)]),
+        });
+
+    (point_lights_buffer, point_light_count_uniform_buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directional_lights(count: usize) -> Vec<DirectionalLight> {
+        (0..count)
+            .map(|i| DirectionalLight::new(Vec3A::new(i as f32, 1.0, 0.0)))
+            .collect()
+    }
+
+    #[test]
+    fn no_directional_lights_leave_the_buffer_zeroed() {
+        assert_eq!(directional_light_count(&[]), 0);
+        assert_eq!(
+            directional_lights_data(&[]),
+            [[0.0; 4]; MAX_DIRECTIONAL_LIGHTS]
+        );
+    }
+
+    #[test]
+    fn directional_lights_are_packed_in_order() {
+        for count in [1, 3] {
+            let lights = directional_lights(count);
+            let data = directional_lights_data(&lights);
+
+            assert_eq!(directional_light_count(&lights), count as u32);
+            for (i, light_data) in data.iter().enumerate() {
+                let expected = if i < count {
+                    [i as f32, 1.0, 0.0, 0.0]
+                } else {
+                    [0.0; 4]
+                };
+                assert_eq!(*light_data, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn directional_lights_past_the_maximum_are_dropped() {
+        let lights = directional_lights(MAX_DIRECTIONAL_LIGHTS + 4);
+        let data = directional_lights_data(&lights);
+
+        assert_eq!(
+            directional_light_count(&lights),
+            MAX_DIRECTIONAL_LIGHTS as u32
+        );
+        assert_eq!(
+            data[MAX_DIRECTIONAL_LIGHTS - 1],
+            [(MAX_DIRECTIONAL_LIGHTS - 1) as f32, 1.0, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn point_light_scalars_fill_the_vec3_padding() {
+        let light = PointLight::new(
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(0.25, 0.5, 0.75),
+            4.0,
+            5.0,
+        );
+        let data = PointLightData::from(&light);
+
+        assert_eq!(
+            bytemuck::cast::<_, [f32; 8]>(data),
+            [1.0, 2.0, 3.0, 4.0, 0.25, 0.5, 0.75, 5.0]
+        );
+    }
+
+    #[test]
+    fn point_lights_past_the_maximum_are_dropped() {
+        let lights: Vec<_> = (0..MAX_POINT_LIGHTS + 2)
+            .map(|i| PointLight::new(Vec3::splat(i as f32), Vec3::ONE, 1.0, 10.0))
+            .collect();
+        let data = point_lights_data(&lights);
+
+        assert_eq!(point_light_count(&lights), MAX_POINT_LIGHTS as u32);
+        assert_eq!(point_light_count(&lights[..2]), 2);
+        assert_eq!(
+            data[MAX_POINT_LIGHTS - 1].position,
+            [(MAX_POINT_LIGHTS - 1) as f32; 3]
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use app::application::{Application, StateInitializationEvent};
+use app::{
+    application::{Application, StateInitializationEvent},
+    wgpu::WgpuOptions,
+};
 
 #[cfg(target_arch = "wasm32")]
 use winit::platform::web::EventLoopExtWebSys;
@@ -40,13 +43,13 @@ pub fn run() {
 
     #[cfg(target_arch = "wasm32")]
     {
-        let application: Application = Application::new(&event_loop);
+        let application: Application = Application::new(&event_loop, WgpuOptions::from_env());
         event_loop.spawn_app(application);
     }
 
     #[cfg(not(target_arch = "wasm32"))]
     {
-        let mut application: Application = Application::new(&event_loop);
+        let mut application: Application = Application::new(&event_loop, WgpuOptions::from_env());
         event_loop.run_app(&mut application).unwrap();
     }
 }
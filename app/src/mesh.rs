@@ -1,12 +1,45 @@
+use std::collections::HashMap;
+
+use glam::Vec3;
 use ply_rs::ply;
 
 use crate::wgpu::Vertex;
 
-pub struct PlyMesh {
+/// Procedurally generated meshes, their front faces are wound counter-clockwise.
+pub mod primitives;
+
+/// A mesh loaded from a file, ready to be uploaded to the vertex and index buffers.
+pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
 }
 
+#[derive(Debug)]
+pub enum MeshLoadError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+    // The renderers can't draw from empty vertex and index buffers
+    NoTriangles,
+}
+
+impl std::fmt::Display for MeshLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{error}"),
+            Self::Parse { line, message } => write!(f, "line {line}: {message}"),
+            Self::NoTriangles => write!(f, "the file has no faces"),
+        }
+    }
+}
+
+impl std::error::Error for MeshLoadError {}
+
+impl From<std::io::Error> for MeshLoadError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
 trait GetPlyPropertyValue {
     fn get_f32(&self) -> f32;
     fn get_u8(&self) -> u8;
@@ -36,18 +69,19 @@ impl GetPlyPropertyValue for Option<&ply::Property> {
     }
 }
 
-impl PlyMesh {
-    pub fn new(path: &str) -> Self {
+impl Mesh {
+    pub fn from_ply(path: &str) -> Self {
         let parser = ply_rs::parser::Parser::<ply_rs::ply::DefaultElement>::new();
         let mut file = std::fs::File::open(path).unwrap();
         let mesh = parser.read_ply(&mut file).unwrap();
+        let has_normals = mesh.header.elements["vertex"].properties.contains_key("nx");
 
-        Self {
+        let mut ply_mesh = Self {
             vertices: mesh
                 .payload
                 .get("vertex")
                 .unwrap()
-                .into_iter()
+                .iter()
                 .map(|vertex| Vertex {
                     position: [
                         vertex.get("x").get_f32(),
@@ -61,19 +95,23 @@ impl PlyMesh {
                         vertex.get("blue").get_u8() as f32 / 255.0,
                         vertex.get("alpha").get_u8() as f32 / 255.0,
                     ],
-                    normal: [
-                        vertex.get("nx").get_f32(),
-                        vertex.get("ny").get_f32(),
-                        vertex.get("nz").get_f32(),
-                        0.0,
-                    ],
+                    normal: if has_normals {
+                        [
+                            vertex.get("nx").get_f32(),
+                            vertex.get("ny").get_f32(),
+                            vertex.get("nz").get_f32(),
+                            0.0,
+                        ]
+                    } else {
+                        [0.0; 4]
+                    },
                 })
                 .collect::<Vec<Vertex>>(),
             indices: mesh
                 .payload
                 .get("face")
                 .unwrap()
-                .into_iter()
+                .iter()
                 .flat_map(|face| {
                     let vertices = face.get("vertex_indices").get_list_u32();
 
@@ -93,8 +131,249 @@ impl PlyMesh {
                     ]
                 })
                 .collect(),
+        };
+
+        if !has_normals {
+            ply_mesh.recompute_normals();
+        }
+
+        ply_mesh
+    }
+
+    pub fn from_obj(path: &str) -> Result<Self, MeshLoadError> {
+        Self::parse_obj(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses the positions, normals and faces of a Wavefront OBJ file.
+    /// Polygons are triangulated as fans and smooth normals are generated for the vertices without one.
+    /// OBJ has no vertex colors, but the common `v x y z r g b` extension is read if present.
+    pub fn parse_obj(source: &str) -> Result<Self, MeshLoadError> {
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut colors: Vec<[f32; 3]> = Vec::new();
+        let mut normals: Vec<[f32; 3]> = Vec::new();
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        // Vertices are shared between faces that use the same position and normal
+        let mut vertex_indices: HashMap<(usize, Option<usize>), u32> = HashMap::new();
+        let mut is_missing_normal = Vec::new();
+
+        for (line_index, line) in source.lines().enumerate() {
+            let line_number = line_index + 1;
+            let parse_error = |message: String| MeshLoadError::Parse {
+                line: line_number,
+                message,
+            };
+
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let values = parse_obj_floats(tokens).map_err(parse_error)?;
+                    match values.as_slice() {
+                        [x, y, z] | [x, y, z, _] => {
+                            positions.push([*x, *y, *z]);
+                            colors.push([1.0, 1.0, 1.0]);
+                        }
+                        [x, y, z, r, g, b] => {
+                            positions.push([*x, *y, *z]);
+                            colors.push([*r, *g, *b]);
+                        }
+                        _ => {
+                            return Err(parse_error(format!(
+                                "expected 3, 4 or 6 values for a vertex, found {}",
+                                values.len()
+                            )))
+                        }
+                    }
+                }
+                Some("vn") => {
+                    let values = parse_obj_floats(tokens).map_err(parse_error)?;
+                    let [x, y, z] = values.as_slice() else {
+                        return Err(parse_error(format!(
+                            "expected 3 values for a normal, found {}",
+                            values.len()
+                        )));
+                    };
+                    normals.push([*x, *y, *z]);
+                }
+                Some("f") => {
+                    let face = tokens
+                        .map(|token| parse_obj_face_vertex(token, positions.len(), normals.len()))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(parse_error)?;
+                    if face.len() < 3 {
+                        return Err(parse_error(format!(
+                            "expected at least 3 vertices for a face, found {}",
+                            face.len()
+                        )));
+                    }
+
+                    let mut face_indices = Vec::with_capacity(face.len());
+                    for (position_index, normal_index) in face {
+                        let index = *vertex_indices
+                            .entry((position_index, normal_index))
+                            .or_insert_with(|| {
+                                let normal = normal_index.map_or(Vec3::ZERO, |normal_index| {
+                                    normals[normal_index].into()
+                                });
+                                vertices.push(Vertex {
+                                    position: Vec3::from(positions[position_index])
+                                        .extend(1.0)
+                                        .to_array(),
+                                    color: Vec3::from(colors[position_index])
+                                        .extend(1.0)
+                                        .to_array(),
+                                    normal: normal.extend(0.0).to_array(),
+                                });
+                                is_missing_normal.push(normal_index.is_none());
+                                vertices.len() as u32 - 1
+                            });
+                        face_indices.push(index);
+                    }
+
+                    // Triangulate the polygon as a fan around its first vertex
+                    for i in 1..face_indices.len() - 1 {
+                        indices.extend([face_indices[0], face_indices[i], face_indices[i + 1]]);
+                    }
+                }
+                // Texture coordinates, groups, materials and comments aren't used
+                _ => (),
+            }
+        }
+
+        if indices.is_empty() {
+            return Err(MeshLoadError::NoTriangles);
         }
+
+        let mut mesh = Self { vertices, indices };
+        if is_missing_normal.contains(&true) {
+            let normals = mesh.smooth_normals();
+            for ((vertex, normal), is_missing_normal) in
+                mesh.vertices.iter_mut().zip(normals).zip(is_missing_normal)
+            {
+                if is_missing_normal {
+                    vertex.normal = normal.extend(0.0).to_array();
+                }
+            }
+        }
+
+        Ok(mesh)
     }
+
+    /// Returns the min and max corners of the local space bounding box of the vertices.
+    /// An empty mesh has a zero sized box at the origin.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        vertex_bounds(&self.vertices)
+    }
+
+    /// Replaces the vertex normals with smooth normals computed from the triangles.
+    pub fn recompute_normals(&mut self) {
+        let normals = self.smooth_normals();
+        for (vertex, normal) in self.vertices.iter_mut().zip(normals) {
+            vertex.normal = normal.extend(0.0).to_array();
+        }
+    }
+
+    // Each triangle contributes its face normal weighted by its area to the vertices it shares
+    fn smooth_normals(&self) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::ZERO; self.vertices.len()];
+
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| {
+                let position = self.vertices[triangle[i] as usize].position;
+                Vec3::new(position[0], position[1], position[2])
+            });
+
+            // The cross product's length is twice the triangle's area
+            let weighted_normal = (b - a).cross(c - a);
+            for &index in triangle {
+                normals[index as usize] += weighted_normal;
+            }
+        }
+
+        normals.into_iter().map(Vec3::normalize_or_zero).collect()
+    }
+}
+
+// Also used for the static meshes, which don't have a `Mesh`
+pub fn vertex_bounds(vertices: &[Vertex]) -> (Vec3, Vec3) {
+    if vertices.is_empty() {
+        return (Vec3::ZERO, Vec3::ZERO);
+    }
+
+    vertices.iter().fold(
+        (Vec3::INFINITY, Vec3::NEG_INFINITY),
+        |(min, max), vertex| {
+            let position = Vec3::from_slice(&vertex.position[..3]);
+            (min.min(position), max.max(position))
+        },
+    )
+}
+
+/// Returns the index pairs of each unique edge of the triangles, for drawing them as a line list.
+pub fn edge_indices(triangle_indices: &[u32]) -> Vec<u32> {
+    let mut edges = triangle_indices
+        .chunks_exact(3)
+        .flat_map(|triangle| {
+            [
+                (triangle[0], triangle[1]),
+                (triangle[1], triangle[2]),
+                (triangle[2], triangle[0]),
+            ]
+        })
+        // Neighboring triangles share edges in opposite directions
+        .map(|(a, b)| (a.min(b), a.max(b)))
+        .collect::<Vec<_>>();
+    edges.sort_unstable();
+    edges.dedup();
+
+    edges.into_iter().flat_map(|(a, b)| [a, b]).collect()
+}
+
+fn parse_obj_floats<'a>(tokens: impl Iterator<Item = &'a str>) -> Result<Vec<f32>, String> {
+    tokens
+        .map(|token| {
+            token
+                .parse::<f32>()
+                .map_err(|_| format!("invalid number `{token}`"))
+        })
+        .collect()
+}
+
+// Resolves a `v`, `v/vt`, `v//vn` or `v/vt/vn` face vertex to zero-based position and normal indices.
+// OBJ indices start at 1, and negative indices are relative to the end of the list.
+fn parse_obj_face_vertex(
+    token: &str,
+    position_count: usize,
+    normal_count: usize,
+) -> Result<(usize, Option<usize>), String> {
+    let resolve_index = |index: &str, count: usize| {
+        let index = index
+            .parse::<isize>()
+            .map_err(|_| format!("invalid index `{index}`"))?;
+        let resolved = if index < 0 {
+            count as isize + index
+        } else {
+            index - 1
+        };
+
+        if (0..count as isize).contains(&resolved) {
+            Ok(resolved as usize)
+        } else {
+            Err(format!("index {index} is out of range"))
+        }
+    };
+
+    let mut parts = token.split('/');
+    let position_index = resolve_index(parts.next().unwrap_or_default(), position_count)?;
+    let normal_index = match parts.nth(1) {
+        Some(normal_index) if !normal_index.is_empty() => {
+            Some(resolve_index(normal_index, normal_count)?)
+        }
+        _ => None,
+    };
+
+    Ok((position_index, normal_index))
 }
 
 pub struct Triangle<'tri> {
@@ -127,6 +406,12 @@ impl<'tri> Triangle<'tri> {
     }
 }
 
+impl Default for Triangle<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Pentagon<'pent> {
     pub vertices: &'pent [Vertex],
     pub indices: &'pent [u32],
@@ -167,6 +452,12 @@ impl<'pent> Pentagon<'pent> {
     }
 }
 
+impl Default for Pentagon<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Cube<'cube> {
     pub vertices: &'cube [Vertex],
     pub indices: &'cube [u32],
@@ -231,6 +522,12 @@ impl<'cube> Cube<'cube> {
     }
 }
 
+impl Default for Cube<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Octahedron<'oct> {
     pub vertices: &'oct [Vertex],
     pub indices: &'oct [u32],
@@ -285,6 +582,12 @@ impl<'oct> Octahedron<'oct> {
     }
 }
 
+impl Default for Octahedron<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct CornellBox<'cornell> {
     pub vertices: &'cornell [Vertex],
     pub indices: &'cornell [u32],
@@ -410,3 +713,135 @@ impl<'cornell> CornellBox<'cornell> {
         }
     }
 }
+
+impl Default for CornellBox<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUBE_OBJ: &str = include_str!("../test_data/cube.obj");
+
+    fn obj_parse_error(source: &str) -> (usize, String) {
+        match Mesh::parse_obj(source) {
+            Err(MeshLoadError::Parse { line, message }) => (line, message),
+            Err(error) => panic!("Expected a parse error, got {error}"),
+            Ok(_) => panic!("Expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn obj_cube_quads_are_triangulated() {
+        let mesh = Mesh::parse_obj(CUBE_OBJ).unwrap();
+
+        // Each corner is split per face, since the faces have different normals
+        assert_eq!(mesh.vertices.len(), 24);
+        assert_eq!(mesh.indices.len(), 6 * 2 * 3);
+        assert!(mesh
+            .indices
+            .iter()
+            .all(|&index| (index as usize) < mesh.vertices.len()));
+        assert_eq!(mesh.vertices[0].normal, [0.0, 0.0, 1.0, 0.0]);
+        assert_eq!(mesh.vertices[0].color, [1.0; 4]);
+    }
+
+    #[test]
+    fn bounds_are_the_min_and_max_vertex_positions() {
+        let mesh = Mesh::parse_obj(CUBE_OBJ).unwrap();
+        assert_eq!(mesh.bounds(), (Vec3::splat(-0.5), Vec3::splat(0.5)));
+
+        let mesh =
+            Mesh::parse_obj("v -1 2 0.5\nv 3 -4 0\nv 0 0 -2\nv 100 100 100\nf 1 2 3\n").unwrap();
+        // Only the vertices the faces use make it into the mesh
+        assert_eq!(
+            mesh.bounds(),
+            (Vec3::new(-1.0, -4.0, -2.0), Vec3::new(3.0, 2.0, 0.5))
+        );
+    }
+
+    #[test]
+    fn empty_mesh_bounds_are_at_the_origin() {
+        let mesh = Mesh {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        };
+
+        assert_eq!(mesh.bounds(), (Vec3::ZERO, Vec3::ZERO));
+    }
+
+    #[test]
+    fn quad_edges_are_shared_between_its_triangles() {
+        let edges = edge_indices(&[0, 1, 2, 0, 2, 3]);
+
+        // The four sides and the diagonal, which both triangles use
+        assert_eq!(edges, [0, 1, 0, 2, 0, 3, 1, 2, 2, 3]);
+        assert!(edge_indices(&[]).is_empty());
+    }
+
+    #[test]
+    fn obj_flat_quad_without_normals_faces_up_z() {
+        let mesh = Mesh::parse_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+
+        assert_eq!(mesh.vertices.len(), 4);
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn obj_normals_from_the_file_are_kept_when_others_are_missing() {
+        // The first triangle's normal deliberately disagrees with its winding
+        let mesh = Mesh::parse_obj(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 1 0 0\nf 1//1 2//1 3//1\nf 1 3 4\n",
+        )
+        .unwrap();
+
+        assert_eq!(mesh.vertices.len(), 6);
+        for vertex in &mesh.vertices[..3] {
+            assert_eq!(vertex.normal, [1.0, 0.0, 0.0, 0.0]);
+        }
+        for vertex in &mesh.vertices[3..] {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn obj_without_faces_is_rejected() {
+        assert!(matches!(
+            Mesh::parse_obj("# Just points\nv 0 0 0\nv 1 0 0\nv 0 1 0\n"),
+            Err(MeshLoadError::NoTriangles)
+        ));
+        assert!(matches!(
+            Mesh::parse_obj(""),
+            Err(MeshLoadError::NoTriangles)
+        ));
+    }
+
+    #[test]
+    fn obj_invalid_number_is_reported_with_its_line() {
+        let (line, message) = obj_parse_error("v 0 0 0\nv 1 abc 0\n");
+
+        assert_eq!(line, 2);
+        assert_eq!(message, "invalid number `abc`");
+    }
+
+    #[test]
+    fn obj_face_index_out_of_range_is_rejected() {
+        let (line, message) = obj_parse_error("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n");
+
+        assert_eq!(line, 4);
+        assert_eq!(message, "index 4 is out of range");
+    }
+
+    #[test]
+    fn obj_face_with_two_vertices_is_rejected() {
+        let (line, message) = obj_parse_error("v 0 0 0\nv 1 0 0\nf 1 2\n");
+
+        assert_eq!(line, 3);
+        assert_eq!(message, "expected at least 3 vertices for a face, found 2");
+    }
+}
@@ -0,0 +1,245 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+use super::Mesh;
+use crate::wgpu::Vertex;
+
+const COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// The primitives the UI can replace the mesh with, at a size that fits the default camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Primitive {
+    Cube,
+    Sphere,
+    Plane,
+}
+
+impl Primitive {
+    pub const ALL: [Self; 3] = [Self::Cube, Self::Sphere, Self::Plane];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cube => "Cube",
+            Self::Sphere => "Sphere",
+            Self::Plane => "Plane",
+        }
+    }
+
+    pub fn mesh(self) -> Mesh {
+        match self {
+            Self::Cube => cube(1.0),
+            Self::Sphere => uv_sphere(0.5, 32, 16),
+            Self::Plane => plane(2.0, 4),
+        }
+    }
+}
+
+fn vertex(position: Vec3, normal: Vec3) -> Vertex {
+    Vertex {
+        position: position.extend(1.0).to_array(),
+        color: COLOR,
+        normal: normal.extend(0.0).to_array(),
+    }
+}
+
+/// A cube centered on the origin, each face has its own vertices so the edges stay sharp.
+pub fn cube(size: f32) -> Mesh {
+    let half_size = size / 2.0;
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+
+    for normal in [
+        Vec3::X,
+        Vec3::NEG_X,
+        Vec3::Y,
+        Vec3::NEG_Y,
+        Vec3::Z,
+        Vec3::NEG_Z,
+    ] {
+        // `u` cross `v` is the normal, so going around the corners in this order is counter-clockwise
+        let u = normal.any_orthonormal_vector();
+        let v = normal.cross(u);
+
+        let first_index = vertices.len() as u32;
+        for (u_sign, v_sign) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let position = (normal + u * u_sign + v * v_sign) * half_size;
+            vertices.push(vertex(position, normal));
+        }
+
+        indices.extend([0, 1, 2, 0, 2, 3].map(|i| first_index + i));
+    }
+
+    Mesh { vertices, indices }
+}
+
+/// A sphere centered on the origin with its poles on the Y axis.
+/// `sectors` is the number of subdivisions around the Y axis and `stacks` from pole to pole.
+pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> Mesh {
+    let sectors = sectors.max(3);
+    let stacks = stacks.max(2);
+
+    // The first and last column of each ring overlap at the seam
+    let mut vertices = Vec::with_capacity(((stacks + 1) * (sectors + 1)) as usize);
+    for stack in 0..=stacks {
+        let polar_angle = PI * stack as f32 / stacks as f32;
+        for sector in 0..=sectors {
+            let azimuthal_angle = 2.0 * PI * sector as f32 / sectors as f32;
+            let normal = Vec3::new(
+                polar_angle.sin() * azimuthal_angle.cos(),
+                polar_angle.cos(),
+                polar_angle.sin() * azimuthal_angle.sin(),
+            );
+            vertices.push(vertex(normal * radius, normal));
+        }
+    }
+
+    let mut indices = Vec::with_capacity((6 * sectors * (stacks - 1)) as usize);
+    for stack in 0..stacks {
+        for sector in 0..sectors {
+            let top = stack * (sectors + 1) + sector;
+            let bottom = top + sectors + 1;
+
+            // The triangles touching a pole would be degenerate
+            if stack != 0 {
+                indices.extend([top, top + 1, bottom]);
+            }
+            if stack != stacks - 1 {
+                indices.extend([top + 1, bottom + 1, bottom]);
+            }
+        }
+    }
+
+    Mesh { vertices, indices }
+}
+
+/// A square on the XZ plane centered on the origin and facing +Y.
+/// `subdivisions` is the number of extra cuts along each side.
+pub fn plane(size: f32, subdivisions: u32) -> Mesh {
+    let cells = subdivisions + 1;
+    let cell_size = size / cells as f32;
+    let half_size = size / 2.0;
+
+    let mut vertices = Vec::with_capacity(((cells + 1) * (cells + 1)) as usize);
+    for row in 0..=cells {
+        for column in 0..=cells {
+            let position = Vec3::new(
+                column as f32 * cell_size - half_size,
+                0.0,
+                row as f32 * cell_size - half_size,
+            );
+            vertices.push(vertex(position, Vec3::Y));
+        }
+    }
+
+    let mut indices = Vec::with_capacity((6 * cells * cells) as usize);
+    for row in 0..cells {
+        for column in 0..cells {
+            let a = row * (cells + 1) + column;
+            let b = a + 1;
+            let d = a + cells + 1;
+            let c = d + 1;
+
+            indices.extend([a, d, c, a, c, b]);
+        }
+    }
+
+    Mesh { vertices, indices }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn position(mesh: &Mesh, index: u32) -> Vec3 {
+        Vec3::from_slice(&mesh.vertices[index as usize].position[..3])
+    }
+
+    // Each edge of a closed surface is shared by exactly two triangles, once in each direction.
+    // The positions are welded first, since the sharp edges and the seam duplicate vertices.
+    fn assert_watertight(mesh: &Mesh) {
+        let welded_index = |index: u32| {
+            let position = position(mesh, index);
+            (position * 1e4).round().as_ivec3().to_array()
+        };
+
+        let mut edge_counts = HashMap::new();
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| welded_index(triangle[i]));
+            assert!(
+                a != b && b != c && c != a,
+                "Degenerate triangle {triangle:?}"
+            );
+
+            for edge in [(a, b), (b, c), (c, a)] {
+                *edge_counts.entry(edge).or_insert(0) += 1;
+            }
+        }
+
+        for (&(a, b), &count) in &edge_counts {
+            assert_eq!(count, 1, "Edge {a:?} {b:?} is used {count} times");
+            assert_eq!(
+                edge_counts.get(&(b, a)),
+                Some(&1),
+                "Edge {a:?} {b:?} is open"
+            );
+        }
+    }
+
+    // Counter-clockwise triangles face the same way as their vertex normals
+    fn assert_counter_clockwise(mesh: &Mesh) {
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| position(mesh, triangle[i]));
+            let face_normal = (b - a).cross(c - a);
+
+            for &index in triangle {
+                let normal = Vec3::from_slice(&mesh.vertices[index as usize].normal[..3]);
+                assert!(
+                    face_normal.dot(normal) > 0.0,
+                    "Triangle {triangle:?} is wound clockwise"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cube_is_closed_and_faces_out() {
+        let mesh = cube(2.0);
+
+        assert_eq!(mesh.vertices.len(), 24);
+        assert_eq!(mesh.indices.len(), 36);
+        assert_eq!(mesh.bounds(), (Vec3::splat(-1.0), Vec3::splat(1.0)));
+        assert_watertight(&mesh);
+        assert_counter_clockwise(&mesh);
+    }
+
+    #[test]
+    fn uv_sphere_is_closed_and_faces_out() {
+        let mesh = uv_sphere(1.5, 12, 6);
+
+        assert_watertight(&mesh);
+        assert_counter_clockwise(&mesh);
+        for vertex in &mesh.vertices {
+            let position = Vec3::from_slice(&vertex.position[..3]);
+            let normal = Vec3::from_slice(&vertex.normal[..3]);
+
+            assert!((position.length() - 1.5).abs() < 1e-5);
+            assert!(normal.abs_diff_eq(position / 1.5, 1e-5));
+        }
+    }
+
+    #[test]
+    fn plane_faces_up() {
+        let mesh = plane(2.0, 2);
+
+        assert_eq!(mesh.vertices.len(), 16);
+        assert_eq!(mesh.indices.len(), 9 * 6);
+        assert_eq!(
+            mesh.bounds(),
+            (Vec3::new(-1.0, 0.0, -1.0), Vec3::new(1.0, 0.0, 1.0))
+        );
+        assert_counter_clockwise(&mesh);
+    }
+}
@@ -1,25 +1,63 @@
-use glam::Vec3A;
 use wgpu::util::DeviceExt;
 
-use crate::{camera::Camera, wgpu::Vertex};
+use crate::{camera::Camera, grid::Grid, renderer::RenderStats, wgpu::Vertex};
 
+/// Which triangles the rasterizer's render pipeline culls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RasterizerCulling {
+    /// `None` draws both sides, which helps when debugging thin or open geometry
+    pub cull_mode: Option<wgpu::Face>,
+    pub front_face: wgpu::FrontFace,
+}
+
+impl Default for RasterizerCulling {
+    // The loaded and procedural meshes are all wound counter-clockwise
+    fn default() -> Self {
+        Self {
+            cull_mode: Some(wgpu::Face::Back),
+            front_face: wgpu::FrontFace::Ccw,
+        }
+    }
+}
+
+impl RasterizerCulling {
+    pub fn primitive_state(self) -> wgpu::PrimitiveState {
+        wgpu::PrimitiveState {
+            front_face: self.front_face,
+            cull_mode: self.cull_mode,
+            ..Default::default()
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn initialize_rasterizer(
     camera: &Camera,
+    culling: RasterizerCulling,
     color_uniform: &[f32; 4],
-    sun_direction_uniform: &Vec3A,
+    directional_lights_buffer: &wgpu::Buffer,
+    directional_light_count_uniform_buffer: &wgpu::Buffer,
+    point_lights_buffer: &wgpu::Buffer,
+    point_light_count_uniform_buffer: &wgpu::Buffer,
     device: &wgpu::Device,
-    surface: &wgpu::Surface,
-    adapter: &wgpu::Adapter,
+    surface_format: wgpu::TextureFormat,
 ) -> (
-    wgpu::Buffer,
     wgpu::Buffer,
     wgpu::Buffer,
     wgpu::BindGroup,
+    wgpu::ShaderModule,
+    wgpu::PipelineLayout,
+    wgpu::RenderPipeline,
     wgpu::RenderPipeline,
 ) {
-    // Load the shaders from disk
-    let rasterizer_shader =
-        device.create_shader_module(wgpu::include_wgsl!("shaders/rasterizer/main.wgsl"));
+    let rasterizer_shader_source = crate::wgpu::with_color_encoding(
+        include_str!("shaders/rasterizer/main.wgsl"),
+        surface_format,
+    );
+    let rasterizer_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Rasterizer Shader"),
+        source: wgpu::ShaderSource::Wgsl(rasterizer_shader_source.into()),
+    });
 
     let camera_view_proj_uniform_buffer =
         device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -34,13 +72,6 @@ pub fn initialize_rasterizer(
         contents: bytemuck::cast_slice(color_uniform),
     });
 
-    let sun_direction_uniform_buffer =
-        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Sun Direction Uniform Buffer"),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            contents: bytemuck::cast_slice(&sun_direction_uniform.to_array()),
-        });
-
     let rasterizer_bind_group_layout =
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Rasterizer Bind Group Layout"),
@@ -68,6 +99,36 @@ pub fn initialize_rasterizer(
                 wgpu::BindGroupLayoutEntry {
                     binding: 2,
                     visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -92,7 +153,19 @@ pub fn initialize_rasterizer(
             },
             wgpu::BindGroupEntry {
                 binding: 2,
-                resource: sun_direction_uniform_buffer.as_entire_binding(),
+                resource: directional_lights_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: directional_light_count_uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: point_lights_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: point_light_count_uniform_buffer.as_entire_binding(),
             },
         ],
     });
@@ -104,54 +177,81 @@ pub fn initialize_rasterizer(
             push_constant_ranges: &[],
         });
 
-    let swapchain_capabilities = surface.get_capabilities(adapter);
-    let swapchain_format = swapchain_capabilities.formats[0];
-
-    let mut primitive = wgpu::PrimitiveState::default();
-    primitive.cull_mode = Some(wgpu::Face::Back);
-
-    let rasterizer_render_pipeline =
-        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Rasterizer Render Pipeline"),
-            layout: Some(&rasterizer_render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &rasterizer_shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-                compilation_options: Default::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &rasterizer_shader,
-                entry_point: "fs_main",
-                compilation_options: Default::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: swapchain_format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive,
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: crate::wgpu::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-            cache: None,
-        });
+    let rasterizer_render_pipeline = create_rasterizer_render_pipeline(
+        "Rasterizer Render Pipeline",
+        culling.primitive_state(),
+        &rasterizer_shader,
+        &rasterizer_render_pipeline_layout,
+        surface_format,
+        device,
+    );
+
+    // Lines have no facing to cull, and unlike `PolygonMode::Line` this works everywhere
+    let rasterizer_wireframe_render_pipeline = create_rasterizer_render_pipeline(
+        "Rasterizer Wireframe Render Pipeline",
+        wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::LineList,
+            ..Default::default()
+        },
+        &rasterizer_shader,
+        &rasterizer_render_pipeline_layout,
+        surface_format,
+        device,
+    );
 
     (
         camera_view_proj_uniform_buffer,
         color_uniform_buffer,
-        sun_direction_uniform_buffer,
         rasterizer_bind_group,
+        rasterizer_shader,
+        rasterizer_render_pipeline_layout,
         rasterizer_render_pipeline,
+        rasterizer_wireframe_render_pipeline,
     )
 }
 
+pub fn create_rasterizer_render_pipeline(
+    label: &str,
+    primitive: wgpu::PrimitiveState,
+    rasterizer_shader: &wgpu::ShaderModule,
+    rasterizer_render_pipeline_layout: &wgpu::PipelineLayout,
+    surface_format: wgpu::TextureFormat,
+    device: &wgpu::Device,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(rasterizer_render_pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: rasterizer_shader,
+            entry_point: "vs_main",
+            buffers: &[Vertex::desc()],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: rasterizer_shader,
+            entry_point: "fs_main",
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: surface_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive,
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: crate::wgpu::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn render_rasterizer(
     render_encoder: &mut wgpu::CommandEncoder,
     surface_texture_view: &wgpu::TextureView,
@@ -159,8 +259,13 @@ pub fn render_rasterizer(
     vertex_buffer: &wgpu::Buffer,
     index_buffer: &wgpu::Buffer,
     num_indices: u32,
+    is_mesh_culled: bool,
     rasterizer_bind_group: &wgpu::BindGroup,
     rasterizer_render_pipeline: &wgpu::RenderPipeline,
+    grid: Option<&Grid>,
+    clear_color: wgpu::Color,
+    timestamp_writes: Option<wgpu::RenderPassTimestampWrites>,
+    render_stats: &mut RenderStats,
 ) {
     let mut rasterizer_rpass = render_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
         label: Some("Rasterizer Render Pass"),
@@ -168,7 +273,7 @@ pub fn render_rasterizer(
             view: surface_texture_view,
             resolve_target: None,
             ops: wgpu::Operations {
-                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                load: wgpu::LoadOp::Clear(clear_color),
                 store: wgpu::StoreOp::Store,
             },
         })],
@@ -180,22 +285,103 @@ pub fn render_rasterizer(
             }),
             stencil_ops: None,
         }),
-        timestamp_writes: None,
+        timestamp_writes,
         occlusion_query_set: None,
     });
 
-    rasterizer_rpass.set_vertex_buffer(0, vertex_buffer.slice(..));
-    rasterizer_rpass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-    rasterizer_rpass.set_bind_group(0, rasterizer_bind_group, &[]);
-    rasterizer_rpass.set_pipeline(rasterizer_render_pipeline);
-    rasterizer_rpass.draw_indexed(0..num_indices, 0, 0..1);
+    // The pass still runs when the mesh is culled, so the frame is cleared
+    if !is_mesh_culled {
+        rasterizer_rpass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        rasterizer_rpass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        rasterizer_rpass.set_bind_group(0, rasterizer_bind_group, &[]);
+        rasterizer_rpass.set_pipeline(rasterizer_render_pipeline);
+        rasterizer_rpass.draw_indexed(0..num_indices, 0, 0..1);
+        render_stats.record_draw(1);
+    }
+
+    // The grid is drawn last so it blends over the scene's depth
+    if let Some(grid) = grid {
+        rasterizer_rpass.set_bind_group(0, &grid.bind_group, &[]);
+        rasterizer_rpass.set_pipeline(&grid.render_pipeline);
+        rasterizer_rpass.draw(0..3, 0..1);
+        render_stats.record_draw(1);
+    }
 }
 
 pub struct Rasterizer {
     pub depth_texture: crate::wgpu::Texture,
     pub camera_view_proj_uniform: wgpu::Buffer,
     pub color_uniform_buffer: wgpu::Buffer,
-    pub sun_direction_uniform_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
+    // Kept so the render pipeline can be rebuilt when the culling changes
+    pub shader: wgpu::ShaderModule,
+    pub render_pipeline_layout: wgpu::PipelineLayout,
+    pub culling: RasterizerCulling,
     pub render_pipeline: wgpu::RenderPipeline,
+    // Draws the mesh's edges with the wireframe index buffer
+    pub wireframe_render_pipeline: wgpu::RenderPipeline,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        raytracer::{RaytracerAccumulation, RaytracerSettings},
+        test_utils,
+    };
+
+    #[test]
+    fn culling_is_applied_to_the_primitive_state() {
+        let default_state = RasterizerCulling::default().primitive_state();
+        assert_eq!(default_state.cull_mode, Some(wgpu::Face::Back));
+        assert_eq!(default_state.front_face, wgpu::FrontFace::Ccw);
+
+        let state = RasterizerCulling {
+            cull_mode: None,
+            front_face: wgpu::FrontFace::Cw,
+        }
+        .primitive_state();
+        assert_eq!(state.cull_mode, None);
+        assert_eq!(state.front_face, wgpu::FrontFace::Cw);
+        // Everything else stays at the triangle list defaults
+        assert_eq!(state.topology, wgpu::PrimitiveTopology::TriangleList);
+        assert_eq!(state.polygon_mode, wgpu::PolygonMode::Fill);
+    }
+
+    #[test]
+    fn wireframe_draws_only_the_edges() {
+        let Some(renderer) = test_utils::headless_renderer(
+            64,
+            48,
+            RaytracerAccumulation::default(),
+            &RaytracerSettings::default(),
+        ) else {
+            return;
+        };
+        let count_drawn_pixels = |is_wireframe_enabled| {
+            let frame = test_utils::render_rasterizer_frame(
+                &renderer,
+                false,
+                is_wireframe_enabled,
+                false,
+                wgpu::Color::BLACK,
+                &mut RenderStats::default(),
+            );
+            frame
+                .pixels()
+                .filter(|pixel| pixel.0 != [0, 0, 0, 255])
+                .count()
+        };
+
+        renderer
+            .wgpu
+            .device
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+        let wireframe_pixels = count_drawn_pixels(true);
+        let error = pollster::block_on(renderer.wgpu.device.pop_error_scope());
+
+        assert!(error.is_none(), "{error:?}");
+        assert!(wireframe_pixels > 0);
+        assert!(wireframe_pixels < count_drawn_pixels(false));
+    }
 }
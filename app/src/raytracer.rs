@@ -1,20 +1,188 @@
+use std::mem::{offset_of, size_of};
+
 use wgpu::util::DeviceExt;
 
 use crate::{
     camera,
+    denoiser::Denoiser,
+    renderer::RenderStats,
     wgpu::{VERTEX_COLOR_OFFSET, VERTEX_NORMAL_OFFSET, VERTEX_STRIDE},
 };
 
-#[cfg(not(target_arch = "wasm32"))]
-use wgpu::TextureFormat::Rgba8Unorm as RaytracerTextureFormat;
+/// The format of the texture the raytracer accumulates its samples into.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RaytracerAccumulation {
+    /// `Rgba8Unorm`, or `R32Float` on the web, for devices that can't bind `Rgba16Float`
+    /// as a read-write storage texture. Radiance is clamped to [0, 1] so it isn't tonemapped.
+    Compatible,
+    /// `Rgba16Float`, keeps the HDR range at half the memory of a full float target
+    #[default]
+    F16,
+}
+
+impl RaytracerAccumulation {
+    /// f16 only has an 11-bit significand, so past roughly this many frames the
+    /// running average's blend factor gets too small for new samples to register.
+    pub const F16_MAX_FRAMES: u32 = 512;
+
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            Self::Compatible if cfg!(target_arch = "wasm32") => wgpu::TextureFormat::R32Float,
+            Self::Compatible => wgpu::TextureFormat::Rgba8Unorm,
+            Self::F16 => wgpu::TextureFormat::Rgba16Float,
+        }
+    }
+
+    // The storage texture format as it's spelled in the shaders
+    pub fn wgsl_format(self) -> &'static str {
+        match self {
+            Self::Compatible if cfg!(target_arch = "wasm32") => "r32float",
+            Self::Compatible => "rgba8unorm",
+            Self::F16 => "rgba16float",
+        }
+    }
+
+    /// The brightest sample the result texture can hold, anything above is clamped.
+    pub fn max_radiance(self) -> f32 {
+        match self {
+            Self::Compatible => 1.0,
+            // The largest finite f16, brighter samples would be stored as infinity
+            Self::F16 => 65504.0,
+        }
+    }
 
-#[cfg(target_arch = "wasm32")]
-use wgpu::TextureFormat::R32Float as RaytracerTextureFormat;
+    pub fn max_frames(self) -> u32 {
+        match self {
+            Self::Compatible => u32::MAX,
+            Self::F16 => Self::F16_MAX_FRAMES,
+        }
+    }
+
+    /// Whether another frame should be accumulated after `frame_count` of them, with the user's
+    /// `max_frames` or the format's own limit, whichever is lower. Lowering `max_frames` below
+    /// the frame count stops accumulating straight away.
+    pub fn is_accumulating(self, frame_count: u32, max_frames: u32) -> bool {
+        frame_count < max_frames.min(self.max_frames())
+    }
+}
+
+/// Options the compute shader reads from a uniform, so they can change without rebuilding the pipelines.
+// Matches the `Settings` struct in the compute shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct RaytracerSettings {
+    pub max_bounces: u32,
+    _padding: [u32; 3],
+    // Rays that miss everything see this instead of the environment map when it's enabled
+    pub background_color: [f32; 3],
+    pub is_background_color_enabled: u32,
+}
+
+// The vec3 starts on a 16 byte boundary, like in WGSL
+const _: () = assert!(size_of::<RaytracerSettings>() == 32);
+const _: () = assert!(offset_of!(RaytracerSettings, background_color) == 16);
+
+impl Default for RaytracerSettings {
+    fn default() -> Self {
+        Self {
+            max_bounces: 8,
+            _padding: [0; 3],
+            background_color: [0.0; 3],
+            is_background_color_enabled: false as u32,
+        }
+    }
+}
+
+// Matches the `Tonemap` struct in the raytracer render shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TonemapUniform {
+    exposure: f32,
+    is_tonemapping_enabled: u32,
+    _padding: [u32; 2],
+}
+
+impl TonemapUniform {
+    /// Only HDR results are tonemapped. Encoding to sRGB is up to the render shader,
+    /// see `with_color_encoding`.
+    pub fn new(exposure: f32, accumulation: RaytracerAccumulation) -> Self {
+        Self {
+            exposure,
+            is_tonemapping_enabled: (accumulation == RaytracerAccumulation::F16) as u32,
+            _padding: [0; 2],
+        }
+    }
+}
+
+// Matches the `CameraLens` struct in the compute shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraLensUniform {
+    aperture: f32,
+    focus_distance: f32,
+    _padding: [f32; 2],
+}
+
+impl CameraLensUniform {
+    pub fn new(camera: &camera::Camera) -> Self {
+        Self {
+            aperture: camera.aperture(),
+            focus_distance: camera.focus_distance(),
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Returns why the raytracer can't run with these capabilities, if it can't.
+/// The raytracer needs compute shaders and a result format that can be bound read-write as a storage texture.
+pub fn check_raytracer_support(
+    downlevel_capabilities: &wgpu::DownlevelCapabilities,
+    result_format_features: wgpu::TextureFormatFeatures,
+) -> Result<(), &'static str> {
+    if !downlevel_capabilities
+        .flags
+        .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+    {
+        return Err("Compute shaders aren't supported");
+    }
+
+    if !result_format_features
+        .allowed_usages
+        .contains(wgpu::TextureUsages::STORAGE_BINDING)
+        || !result_format_features
+            .flags
+            .contains(wgpu::TextureFormatFeatureFlags::STORAGE_READ_WRITE)
+    {
+        return Err("Read-write storage textures aren't supported");
+    }
+
+    Ok(())
+}
+
+/// Returns what the device can do with the accumulation's result texture format.
+pub fn result_format_features(
+    accumulation: RaytracerAccumulation,
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+) -> wgpu::TextureFormatFeatures {
+    // Without this feature only the format features guaranteed by WebGPU can be relied on
+    if device
+        .features()
+        .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+    {
+        adapter.get_texture_format_features(accumulation.texture_format())
+    } else {
+        accumulation
+            .texture_format()
+            .guaranteed_format_features(device.features())
+    }
+}
 
 pub fn create_raytracer_result_texture(
     device: &wgpu::Device,
     width: u32,
     height: u32,
+    accumulation: RaytracerAccumulation,
 ) -> (wgpu::Texture, wgpu::TextureView) {
     // Create storage texture for the raytracer to write to
     let result_texture = device.create_texture(&wgpu::TextureDescriptor {
@@ -27,7 +195,7 @@ pub fn create_raytracer_result_texture(
         mip_level_count: 1,
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
-        format: RaytracerTextureFormat,
+        format: accumulation.texture_format(),
         usage: wgpu::TextureUsages::STORAGE_BINDING
             | wgpu::TextureUsages::COPY_DST
             | wgpu::TextureUsages::COPY_SRC,
@@ -38,16 +206,21 @@ pub fn create_raytracer_result_texture(
     (result_texture, result_texture_view)
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn initialize_raytracer(
+    accumulation: RaytracerAccumulation,
     frame_count: usize,
     vertex_buffer: &wgpu::Buffer,
     index_buffer: &wgpu::Buffer,
     camera: &camera::Camera,
-    sun_direction: &glam::Vec3A,
+    settings: &RaytracerSettings,
+    exposure: f32,
+    directional_lights_buffer: &wgpu::Buffer,
+    directional_light_count_uniform_buffer: &wgpu::Buffer,
+    environment_texture: &crate::wgpu::Texture,
     result_texture_view: &wgpu::TextureView,
     device: &wgpu::Device,
-    surface: &wgpu::Surface,
-    adapter: &wgpu::Adapter,
+    surface_format: wgpu::TextureFormat,
 ) -> (
     wgpu::BindGroupLayout,
     wgpu::BindGroup,
@@ -59,46 +232,49 @@ pub fn initialize_raytracer(
     wgpu::Buffer,
     wgpu::Buffer,
     wgpu::Buffer,
+    wgpu::Buffer,
+    wgpu::Buffer,
     wgpu::BindGroupLayout,
     wgpu::BindGroup,
     wgpu::ComputePipeline,
 ) {
-    let raytracer_render_shader_source = include_str!("shaders/raytracer/render.wgsl");
+    let raytracer_render_shader_source = crate::wgpu::with_color_encoding(
+        include_str!("shaders/raytracer/render.wgsl"),
+        surface_format,
+    );
     let raytracer_compute_shader_source = include_str!("shaders/raytracer/compute.wgsl");
 
-    #[allow(unused_mut, unused_assignments)]
-    let mut raytracer_render_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+    // Replace the storage texture format to match the result texture
+    let result_declaration = format!(
+        "var result: texture_storage_2d<{}",
+        accumulation.wgsl_format()
+    );
+    let raytracer_render_shader_source = raytracer_render_shader_source.replace(
+        "var result: texture_storage_2d<rgba8unorm",
+        &result_declaration,
+    );
+    let raytracer_compute_shader_source = raytracer_compute_shader_source
+        .replace(
+            "var result: texture_storage_2d<rgba8unorm",
+            &result_declaration,
+        )
+        .replace(
+            "const MAX_RADIANCE: f32 = 1.0;",
+            &format!(
+                "const MAX_RADIANCE: f32 = {:?};",
+                accumulation.max_radiance()
+            ),
+        );
+
+    let raytracer_render_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: Some("Raytracer Render Shader"),
         source: wgpu::ShaderSource::Wgsl(raytracer_render_shader_source.into()),
     });
-    #[allow(unused_mut, unused_assignments)]
-    let mut raytracer_compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+    let raytracer_compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: Some("Raytracer Compute Shader"),
         source: wgpu::ShaderSource::Wgsl(raytracer_compute_shader_source.into()),
     });
 
-    #[cfg(target_arch = "wasm32")]
-    {
-        // Replace the storage texture format with r32float for WebGPU
-        let raytracer_render_shader_source = raytracer_render_shader_source.replace(
-            "var result: texture_storage_2d<rgba8unorm",
-            "var result: texture_storage_2d<r32float",
-        );
-        let raytracer_compute_shader_source = raytracer_compute_shader_source.replace(
-            "var result: texture_storage_2d<rgba8unorm",
-            "var result: texture_storage_2d<r32float",
-        );
-
-        raytracer_render_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Raytracer Render Shader"),
-            source: wgpu::ShaderSource::Wgsl(raytracer_render_shader_source.into()),
-        });
-        raytracer_compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Raytracer Compute Shader"),
-            source: wgpu::ShaderSource::Wgsl(raytracer_compute_shader_source.into()),
-        });
-    }
-
     let frame_count_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("Frame Count Uniform Buffer"),
         usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
@@ -142,26 +318,52 @@ pub fn initialize_raytracer(
                 .to_cols_array_2d()]),
         });
 
-    let sun_direction_uniform_buffer =
-        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Sun Direction Uniform Buffer"),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            contents: bytemuck::cast_slice(&[sun_direction.extend(0.0).to_array()]),
-        });
+    let camera_lens_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Camera Lens Uniform Buffer"),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        contents: bytemuck::cast_slice(&[CameraLensUniform::new(camera)]),
+    });
+
+    let settings_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Raytracer Settings Uniform Buffer"),
+        // Also copied from so the tests can check what the shader reads
+        usage: wgpu::BufferUsages::UNIFORM
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
+        contents: bytemuck::cast_slice(&[*settings]),
+    });
+
+    let tonemap_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Tonemap Uniform Buffer"),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        contents: bytemuck::cast_slice(&[TonemapUniform::new(exposure, accumulation)]),
+    });
 
     let raytracer_render_bind_group_layout =
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Raytracer Render Bind Group Layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::StorageTexture {
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    format: RaytracerTextureFormat,
-                    access: wgpu::StorageTextureAccess::ReadOnly,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::StorageTexture {
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        format: accumulation.texture_format(),
+                        access: wgpu::StorageTextureAccess::ReadOnly,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
         });
     let raytracer_compute_bind_group_layout =
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -251,7 +453,7 @@ pub fn initialize_raytracer(
                     binding: 8,
                     visibility: wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
@@ -260,13 +462,59 @@ pub fn initialize_raytracer(
                 wgpu::BindGroupLayoutEntry {
                     binding: 9,
                     visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::StorageTexture {
                         view_dimension: wgpu::TextureViewDimension::D2,
-                        format: RaytracerTextureFormat,
+                        format: accumulation.texture_format(),
                         access: wgpu::StorageTextureAccess::ReadWrite,
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 11,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 12,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 13,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 14,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -283,7 +531,12 @@ pub fn initialize_raytracer(
         &vertex_normal_offset_uniform_buffer,
         &camera_to_world_uniform_buffer,
         &camera_inverse_projection_uniform_buffer,
-        &sun_direction_uniform_buffer,
+        &camera_lens_uniform_buffer,
+        directional_lights_buffer,
+        directional_light_count_uniform_buffer,
+        environment_texture,
+        &settings_uniform_buffer,
+        &tonemap_uniform_buffer,
     );
 
     let raytracer_render_pipeline_layout =
@@ -299,9 +552,6 @@ pub fn initialize_raytracer(
             push_constant_ranges: &[],
         });
 
-    let swapchain_capabilities = surface.get_capabilities(adapter);
-    let swapchain_format = swapchain_capabilities.formats[0];
-
     let raytracer_render_pipeline =
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Raytracer Render Pipeline"),
@@ -317,7 +567,7 @@ pub fn initialize_raytracer(
                 entry_point: "fs_main",
                 compilation_options: Default::default(),
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: swapchain_format,
+                    format: surface_format,
                     blend: Some(wgpu::BlendState::ALPHA_BLENDING),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
@@ -348,13 +598,16 @@ pub fn initialize_raytracer(
         vertex_normal_offset_uniform_buffer,
         camera_to_world_uniform_buffer,
         camera_inverse_projection_uniform_buffer,
-        sun_direction_uniform_buffer,
+        camera_lens_uniform_buffer,
+        settings_uniform_buffer,
+        tonemap_uniform_buffer,
         raytracer_compute_bind_group_layout,
         raytracer_compute_bind_group,
         raytracer_compute_pipeline,
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_raytracer_bind_groups(
     result_texture_view: &wgpu::TextureView,
     device: &wgpu::Device,
@@ -368,15 +621,26 @@ pub fn create_raytracer_bind_groups(
     vertex_normal_offset_uniform_buffer: &wgpu::Buffer,
     camera_to_world_uniform_buffer: &wgpu::Buffer,
     camera_inverse_projection_uniform_buffer: &wgpu::Buffer,
-    sun_direction_uniform_buffer: &wgpu::Buffer,
+    camera_lens_uniform_buffer: &wgpu::Buffer,
+    directional_lights_buffer: &wgpu::Buffer,
+    directional_light_count_uniform_buffer: &wgpu::Buffer,
+    environment_texture: &crate::wgpu::Texture,
+    settings_uniform_buffer: &wgpu::Buffer,
+    tonemap_uniform_buffer: &wgpu::Buffer,
 ) -> (wgpu::BindGroup, wgpu::BindGroup) {
     let raytracer_render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
         label: Some("Raytracer Render Bind Group"),
         layout: raytracer_render_bind_group_layout,
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: wgpu::BindingResource::TextureView(result_texture_view),
-        }],
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(result_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: tonemap_uniform_buffer.as_entire_binding(),
+            },
+        ],
     });
     let raytracer_compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
         label: Some("Raytracer Compute Bind Group"),
@@ -416,12 +680,32 @@ pub fn create_raytracer_bind_groups(
             },
             wgpu::BindGroupEntry {
                 binding: 8,
-                resource: sun_direction_uniform_buffer.as_entire_binding(),
+                resource: directional_lights_buffer.as_entire_binding(),
             },
             wgpu::BindGroupEntry {
                 binding: 9,
+                resource: directional_light_count_uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
                 resource: wgpu::BindingResource::TextureView(result_texture_view),
             },
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: wgpu::BindingResource::TextureView(&environment_texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 12,
+                resource: wgpu::BindingResource::Sampler(&environment_texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 13,
+                resource: settings_uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 14,
+                resource: camera_lens_uniform_buffer.as_entire_binding(),
+            },
         ],
     });
 
@@ -433,6 +717,9 @@ pub fn render_raytracer(
     surface_texture_view: &wgpu::TextureView,
     raytracer_render_bind_group: &wgpu::BindGroup,
     raytracer_render_pipeline: &wgpu::RenderPipeline,
+    clear_color: wgpu::Color,
+    timestamp_writes: Option<wgpu::RenderPassTimestampWrites>,
+    render_stats: &mut RenderStats,
 ) {
     let mut raytracer_rpass = render_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
         label: Some("Raytracer Render Pass"),
@@ -440,18 +727,30 @@ pub fn render_raytracer(
             view: surface_texture_view,
             resolve_target: None,
             ops: wgpu::Operations {
-                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                load: wgpu::LoadOp::Clear(clear_color),
                 store: wgpu::StoreOp::Store,
             },
         })],
         depth_stencil_attachment: None,
-        timestamp_writes: None,
+        timestamp_writes,
         occlusion_query_set: None,
     });
 
     raytracer_rpass.set_bind_group(0, raytracer_render_bind_group, &[]);
     raytracer_rpass.set_pipeline(raytracer_render_pipeline);
     raytracer_rpass.draw(0..3, 0..1);
+    render_stats.record_draw(1);
+}
+
+// Matches the `@workgroup_size` of the compute shader
+const RAYTRACER_WORKGROUP_SIZE: u32 = 8;
+
+/// Returns the number of workgroups needed to cover every pixel of the result texture.
+pub fn raytracer_workgroup_count(width: u32, height: u32) -> (u32, u32) {
+    (
+        width.div_ceil(RAYTRACER_WORKGROUP_SIZE),
+        height.div_ceil(RAYTRACER_WORKGROUP_SIZE),
+    )
 }
 
 pub fn run_raytracer(
@@ -460,6 +759,8 @@ pub fn run_raytracer(
     window_size: winit::dpi::PhysicalSize<u32>,
     raytracer_compute_bind_group: &wgpu::BindGroup,
     raytracer_compute_pipeline: &wgpu::ComputePipeline,
+    timestamp_writes: Option<wgpu::ComputePassTimestampWrites>,
+    render_stats: &mut RenderStats,
 ) {
     let mut compute_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
         label: Some("Compute Command Encoder"),
@@ -469,20 +770,25 @@ pub fn run_raytracer(
         let mut raytracer_cpass =
             compute_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some("Raytracer Compute Pass"),
-                timestamp_writes: None,
+                timestamp_writes,
             });
 
         raytracer_cpass.set_bind_group(0, raytracer_compute_bind_group, &[]);
         raytracer_cpass.set_pipeline(raytracer_compute_pipeline);
-        raytracer_cpass.dispatch_workgroups(window_size.width / 8, window_size.height / 8, 1);
+        let (workgroups_x, workgroups_y) =
+            raytracer_workgroup_count(window_size.width, window_size.height);
+        raytracer_cpass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+        render_stats.record_dispatch(workgroups_x, workgroups_y, 1);
     }
 
     queue.submit(Some(compute_encoder.finish()));
 }
 
 pub struct Raytracer {
+    pub accumulation: RaytracerAccumulation,
     pub result_texture: wgpu::Texture,
     pub result_texture_view: wgpu::TextureView,
+    pub environment_texture: crate::wgpu::Texture,
     pub render_bind_group_layout: wgpu::BindGroupLayout,
     pub render_bind_group: wgpu::BindGroup,
     pub render_pipeline: wgpu::RenderPipeline,
@@ -492,8 +798,267 @@ pub struct Raytracer {
     pub vertex_normal_offset_uniform_buffer: wgpu::Buffer,
     pub camera_to_world_uniform_buffer: wgpu::Buffer,
     pub camera_inverse_projection_uniform_buffer: wgpu::Buffer,
-    pub sun_direction_uniform_buffer: wgpu::Buffer,
+    pub camera_lens_uniform_buffer: wgpu::Buffer,
+    pub settings_uniform_buffer: wgpu::Buffer,
+    pub tonemap_uniform_buffer: wgpu::Buffer,
     pub compute_bind_group_layout: wgpu::BindGroupLayout,
     pub compute_bind_group: wgpu::BindGroup,
     pub compute_pipeline: wgpu::ComputePipeline,
+    pub denoiser: Denoiser,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn accumulation_stops_at_the_lower_frame_limit() {
+        let accumulation = RaytracerAccumulation::Compatible;
+        assert!(accumulation.is_accumulating(0, 256));
+        assert!(accumulation.is_accumulating(255, 256));
+        assert!(!accumulation.is_accumulating(256, 256));
+        // Lowered below the frames accumulated so far
+        assert!(!accumulation.is_accumulating(100, 50));
+
+        let f16_max_frames = RaytracerAccumulation::F16_MAX_FRAMES;
+        let accumulation = RaytracerAccumulation::F16;
+        assert!(accumulation.is_accumulating(f16_max_frames - 1, u32::MAX));
+        assert!(!accumulation.is_accumulating(f16_max_frames, u32::MAX));
+    }
+
+    fn read_write_storage_features() -> wgpu::TextureFormatFeatures {
+        wgpu::TextureFormatFeatures {
+            allowed_usages: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+            flags: wgpu::TextureFormatFeatureFlags::STORAGE_READ_WRITE,
+        }
+    }
+
+    #[test]
+    fn raytracer_runs_with_compute_and_read_write_storage() {
+        assert_eq!(
+            check_raytracer_support(
+                &wgpu::DownlevelCapabilities::default(),
+                read_write_storage_features()
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn raytracer_needs_compute_shaders() {
+        // Like WebGL2, which has no compute stage
+        let mut downlevel_capabilities = wgpu::DownlevelCapabilities::default();
+        downlevel_capabilities.flags -= wgpu::DownlevelFlags::COMPUTE_SHADERS;
+
+        assert_eq!(
+            check_raytracer_support(&downlevel_capabilities, read_write_storage_features()),
+            Err("Compute shaders aren't supported")
+        );
+    }
+
+    #[test]
+    fn raytracer_needs_read_write_storage_textures() {
+        let write_only_features = wgpu::TextureFormatFeatures {
+            flags: wgpu::TextureFormatFeatureFlags::empty(),
+            ..read_write_storage_features()
+        };
+        let unbindable_features = wgpu::TextureFormatFeatures {
+            allowed_usages: wgpu::TextureUsages::COPY_DST,
+            ..read_write_storage_features()
+        };
+
+        for result_format_features in [write_only_features, unbindable_features] {
+            assert_eq!(
+                check_raytracer_support(
+                    &wgpu::DownlevelCapabilities::default(),
+                    result_format_features
+                ),
+                Err("Read-write storage textures aren't supported")
+            );
+        }
+    }
+
+    #[test]
+    fn workgroups_cover_partial_tiles() {
+        assert_eq!(raytracer_workgroup_count(1920, 1017), (240, 128));
+        assert_eq!(raytracer_workgroup_count(8, 8), (1, 1));
+        assert_eq!(raytracer_workgroup_count(1, 9), (1, 2));
+    }
+
+    #[test]
+    fn result_texture_matches_the_accumulation_format() {
+        let Some((_, device, _)) = test_utils::device(wgpu::Features::empty()) else {
+            return;
+        };
+
+        for (accumulation, format) in [
+            (
+                RaytracerAccumulation::Compatible,
+                wgpu::TextureFormat::Rgba8Unorm,
+            ),
+            (RaytracerAccumulation::F16, wgpu::TextureFormat::Rgba16Float),
+        ] {
+            let (texture, _) = create_raytracer_result_texture(&device, 32, 16, accumulation);
+
+            assert_eq!(texture.format(), format);
+            assert_eq!(texture.size().width, 32);
+            assert_eq!(texture.size().height, 16);
+            assert!(texture
+                .usage()
+                .contains(wgpu::TextureUsages::STORAGE_BINDING));
+        }
+    }
+
+    // Mirrors `halton` in the compute shader
+    fn halton(index: u32, base: u32) -> f32 {
+        let mut fraction = 1.0;
+        let mut result = 0.0;
+        let mut i = index;
+        while i > 0 {
+            fraction /= base as f32;
+            result += fraction * (i % base) as f32;
+            i /= base;
+        }
+
+        result
+    }
+
+    // Mirrors `get_pixel_jitter` in the compute shader
+    fn pixel_jitter(frame: u32) -> glam::Vec2 {
+        glam::Vec2::new(halton(frame + 1, 2), halton(frame + 1, 3))
+    }
+
+    #[test]
+    fn pixel_jitter_stays_in_the_pixel_and_changes_every_frame() {
+        assert_eq!(pixel_jitter(0), glam::Vec2::new(0.5, 1.0 / 3.0));
+        assert_eq!(pixel_jitter(1), glam::Vec2::new(0.25, 2.0 / 3.0));
+
+        let jitters: Vec<_> = (0..RaytracerAccumulation::F16_MAX_FRAMES)
+            .map(pixel_jitter)
+            .collect();
+        for jitter in &jitters {
+            assert!(
+                jitter.cmpge(glam::Vec2::ZERO).all() && jitter.cmplt(glam::Vec2::ONE).all(),
+                "{jitter}"
+            );
+        }
+        for (i, jitter) in jitters.iter().enumerate() {
+            assert!(
+                !jitters[..i].contains(jitter),
+                "Frame {i} repeats the offset {jitter}"
+            );
+        }
+    }
+
+    // Mirrors `create_camera_ray` in the compute shader, but stays in camera space
+    fn camera_ray(
+        camera: &camera::Camera,
+        uv: glam::Vec2,
+        lens_sample: glam::Vec2,
+    ) -> (glam::Vec3, glam::Vec3) {
+        let inverse_projection = camera.camera_inverse_projection();
+        let near = inverse_projection * uv.extend(-1.0).extend(1.0);
+        let far = inverse_projection * uv.extend(1.0).extend(1.0);
+        let mut near_position = near.truncate() / near.w;
+        let mut direction = (far.truncate() / far.w - near_position).normalize();
+
+        if camera.aperture() > 0.0 {
+            let focus_t = (camera.focus_distance() + near_position.z) / -direction.z;
+            let focus_position = near_position + direction * focus_t;
+            near_position += (lens_sample * camera.aperture() * 0.5).extend(0.0);
+            direction = (focus_position - near_position).normalize();
+        }
+
+        (near_position, direction)
+    }
+
+    // Where the ray crosses the plane at the depth, the camera looks down -Z
+    fn point_at_depth((origin, direction): (glam::Vec3, glam::Vec3), depth: f32) -> glam::Vec3 {
+        origin + direction * (-depth - origin.z) / direction.z
+    }
+
+    #[test]
+    fn lens_samples_focus_on_the_pinhole_ray_at_the_focus_distance() {
+        let mut camera = test_utils::camera(64, 48);
+        let uv = glam::Vec2::new(0.3, -0.2);
+        let pinhole_ray = camera_ray(&camera, uv, glam::Vec2::ZERO);
+
+        camera.set_aperture(0.5);
+        camera.set_focus_distance(4.0);
+        let focus_position = point_at_depth(pinhole_ray, 4.0);
+        let out_of_focus_position = point_at_depth(pinhole_ray, 8.0);
+
+        for lens_sample in [
+            glam::Vec2::new(1.0, 0.0),
+            glam::Vec2::new(-0.6, 0.8),
+            glam::Vec2::new(0.3, -0.4),
+        ] {
+            let lens_ray = camera_ray(&camera, uv, lens_sample);
+
+            assert_ne!(lens_ray.0, pinhole_ray.0);
+            assert!(
+                point_at_depth(lens_ray, 4.0).abs_diff_eq(focus_position, 1e-4),
+                "{lens_sample} doesn't focus on {focus_position}"
+            );
+            // Anything further away is blurred
+            assert!(!point_at_depth(lens_ray, 8.0).abs_diff_eq(out_of_focus_position, 1e-2));
+        }
+    }
+
+    #[test]
+    fn zero_aperture_is_a_pinhole() {
+        let mut camera = test_utils::camera(64, 48);
+        let uv = glam::Vec2::new(-0.5, 0.7);
+        let pinhole_ray = camera_ray(&camera, uv, glam::Vec2::ZERO);
+
+        camera.set_focus_distance(2.0);
+        assert_eq!(camera_ray(&camera, uv, glam::Vec2::ONE), pinhole_ray);
+    }
+
+    // Mirrors the Russian roulette in the compute shader's bounce loop, returns the path's new
+    // roulette weight if it survives the random number
+    fn russian_roulette(throughput: glam::Vec3, roulette_weight: f32, random: f32) -> Option<f32> {
+        // RUSSIAN_ROULETTE_MIN_PROBABILITY
+        let survival_probability = throughput.max_element().clamp(0.05, 1.0);
+
+        (random <= survival_probability).then_some(roulette_weight / survival_probability)
+    }
+
+    #[test]
+    fn surviving_paths_are_scaled_by_their_survival_probability() {
+        let survival_weight = |throughput| russian_roulette(throughput, 1.0, 0.0).unwrap();
+
+        assert_eq!(survival_weight(glam::Vec3::new(0.5, 0.2, 0.1)), 2.0);
+        // Bright paths always survive and dark ones survive at least sometimes
+        assert_eq!(survival_weight(glam::Vec3::splat(2.0)), 1.0);
+        assert_eq!(survival_weight(glam::Vec3::splat(0.01)), 20.0);
+        // The weight builds up over the bounces
+        assert_eq!(
+            russian_roulette(glam::Vec3::splat(0.25), 2.0, 0.0),
+            Some(8.0)
+        );
+    }
+
+    #[test]
+    fn russian_roulette_keeps_the_average_weight() {
+        let samples = 10_000;
+
+        for throughput in [0.8, 0.3, 0.01] {
+            let total_weight: f32 = (0..samples)
+                .filter_map(|i| {
+                    let random = (i as f32 + 0.5) / samples as f32;
+                    russian_roulette(glam::Vec3::splat(throughput), 1.0, random)
+                })
+                .sum();
+
+            let average_weight = total_weight / samples as f32;
+            assert!(
+                (average_weight - 1.0).abs() < 1e-2,
+                "{throughput} averages {average_weight}"
+            );
+        }
+    }
 }
@@ -5,202 +5,1051 @@ use winit::window::Window;
 
 use crate::{
     camera::Camera,
-    egui::initialize_egui,
+    denoiser::{
+        create_denoiser_bind_groups, create_denoiser_textures, initialize_denoiser, Denoiser,
+    },
+    environment::EnvironmentMap,
+    gpu_timer::GpuTimer,
+    grid::{self, initialize_grid, GridSpacing},
     lights,
-    rasterizer::{self, initialize_rasterizer},
-    raytracer::{self, create_raytracer_result_texture, initialize_raytracer},
+    rasterizer::{
+        self, create_rasterizer_render_pipeline, initialize_rasterizer, RasterizerCulling,
+    },
+    raytracer::{
+        self, check_raytracer_support, create_raytracer_bind_groups,
+        create_raytracer_result_texture, initialize_raytracer, result_format_features,
+        RaytracerAccumulation, RaytracerSettings,
+    },
+    wgpu::{RenderTarget, WgpuOptions},
 };
 
+/// Per-frame counters of the work the scene submits to the GPU.
+/// egui's draws aren't counted, it records its own passes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    pub instances: u32,
+    pub compute_workgroups: u32,
+}
+
+impl RenderStats {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn record_draw(&mut self, instance_count: u32) {
+        self.draw_calls += 1;
+        self.instances += instance_count;
+    }
+
+    pub fn record_dispatch(&mut self, x: u32, y: u32, z: u32) {
+        self.compute_workgroups += x * y * z;
+    }
+}
+
 pub struct Renderer {
     pub rasterizer: rasterizer::Rasterizer,
-    pub raytracer: raytracer::Raytracer,
+    pub grid: grid::Grid,
+    // Holds why the raytracer is unavailable when the device can't run it or its pipelines failed to validate
+    pub raytracer: Result<raytracer::Raytracer, String>,
+    // WebGPU reports whether a recreated raytracer validated asynchronously, the error lands here
+    #[cfg(target_arch = "wasm32")]
+    raytracer_validation_error: std::rc::Rc<std::cell::RefCell<Option<String>>>,
+    // Set by wgpu's device lost callback, which can run on another thread
+    device_lost_message: Arc<std::sync::Mutex<Option<String>>>,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_indices: u32,
-    pub egui: crate::egui::RendererEguiResources,
+    // The mesh's edges as a line list, for the wireframe
+    pub wireframe_index_buffer: wgpu::Buffer,
+    pub num_wireframe_indices: u32,
+    // The min and max corners of the mesh's bounding box
+    pub mesh_bounds: (glam::Vec3, glam::Vec3),
+    pub directional_lights_buffer: wgpu::Buffer,
+    pub directional_light_count_uniform_buffer: wgpu::Buffer,
+    pub point_lights_buffer: wgpu::Buffer,
+    pub point_light_count_uniform_buffer: wgpu::Buffer,
+    // Only available if the device supports timestamp queries
+    pub gpu_timer: Option<GpuTimer>,
     pub wgpu: crate::wgpu::RendererWgpuResources,
 }
 
 impl Renderer {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         window: Arc<Window>,
         window_size: &winit::dpi::PhysicalSize<u32>,
         camera: &Camera,
         color_uniform: &[f32; 4],
-        sun_light: &lights::DirectionalLight,
+        directional_lights: &[lights::DirectionalLight],
+        point_lights: &[lights::PointLight],
+        raytracer_accumulation: RaytracerAccumulation,
+        raytracer_settings: &RaytracerSettings,
+        raytracer_exposure: f32,
+        grid_fade_distance: f32,
+        grid_spacing: GridSpacing,
+        wgpu_options: &WgpuOptions,
     ) -> Self {
-        let instance = wgpu::Instance::default();
-        let surface = instance.create_surface(window.clone()).unwrap();
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                force_fallback_adapter: false,
-                // Request an adapter which can render to our surface
-                compatible_surface: Some(&surface),
-            })
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu_options.backends,
+            ..Default::default()
+        });
+        let surface = instance.create_surface(window).unwrap();
+        // Request an adapter which can render to our surface
+        let adapter = request_adapter(&instance, Some(&surface), wgpu_options)
             .await
             .expect("Failed to find an appropriate adapter");
-
-        // Create the logical device and command queue
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: Some("Device"),
-                    required_features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES, // This can be removed when wgpu is upgraded to the next version.
-                    // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the swapchain.
-                    required_limits: wgpu::Limits::default().using_resolution(adapter.limits()),
-                    memory_hints: wgpu::MemoryHints::Performance,
-                },
-                None,
-            )
+        let (device, queue) = request_device(&adapter, wgpu_options)
             .await
             .expect("Failed to create device");
 
-        let surface_config = surface
+        let mut surface_config = surface
             .get_default_config(&adapter, window_size.width, window_size.height)
             .expect("Failed to get default surface configuration");
+        surface_config.format =
+            crate::wgpu::preferred_surface_format(&surface.get_capabilities(&adapter).formats);
+        // Lets frames be captured where the surface allows it
+        if surface
+            .get_capabilities(&adapter)
+            .usages
+            .contains(wgpu::TextureUsages::COPY_SRC)
+        {
+            surface_config.usage |= wgpu::TextureUsages::COPY_SRC;
+        }
         surface.configure(&device, &surface_config);
 
-        let (egui_renderer, egui_state) = initialize_egui(
-            &window,
-            &device,
-            &surface_config,
-            window.scale_factor() as f32,
-        );
+        #[allow(unused_mut)]
+        let mut renderer = Self::with_target(
+            crate::wgpu::RendererWgpuResources {
+                instance,
+                target: RenderTarget::Surface(surface),
+                adapter,
+                device,
+                queue,
+                surface_config,
+            },
+            camera,
+            color_uniform,
+            directional_lights,
+            point_lights,
+            raytracer_accumulation,
+            raytracer_settings,
+            raytracer_exposure,
+            grid_fade_distance,
+            grid_spacing,
+        )
+        .await;
+
+        // The built in Cornell box stands in on the web, where there are no assets to load
+        #[cfg(not(target_arch = "wasm32"))]
+        renderer.set_mesh(&crate::mesh::Mesh::from_ply("assets/cornell-box.ply"));
+
+        renderer
+    }
+
+    /// Renders into a texture instead of a window's surface, so frames can be read back without a window.
+    /// It starts with the built in Cornell box, nothing is loaded from the working directory.
+    /// Returns `None` if there is no adapter or its device can't be created.
+    ///
+    /// This is the headless entry point rather than the engine, which needs a window for the egui state,
+    /// its input and its redraws. The scene is passed in like the engine does for `new`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new_headless(
+        width: u32,
+        height: u32,
+        camera: &Camera,
+        color_uniform: &[f32; 4],
+        directional_lights: &[lights::DirectionalLight],
+        point_lights: &[lights::PointLight],
+        raytracer_accumulation: RaytracerAccumulation,
+        raytracer_settings: &RaytracerSettings,
+        raytracer_exposure: f32,
+        grid_fade_distance: f32,
+        grid_spacing: GridSpacing,
+        wgpu_options: &WgpuOptions,
+    ) -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu_options.backends,
+            ..Default::default()
+        });
+        let adapter = request_adapter(&instance, None, wgpu_options).await?;
+        let (device, queue) = request_device(&adapter, wgpu_options).await.ok()?;
+
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: RenderTarget::HEADLESS_FORMAT,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: Vec::new(),
+        };
+        let target = RenderTarget::create_texture(&device, &surface_config);
+
+        Some(
+            Self::with_target(
+                crate::wgpu::RendererWgpuResources {
+                    instance,
+                    target,
+                    adapter,
+                    device,
+                    queue,
+                    surface_config,
+                },
+                camera,
+                color_uniform,
+                directional_lights,
+                point_lights,
+                raytracer_accumulation,
+                raytracer_settings,
+                raytracer_exposure,
+                grid_fade_distance,
+                grid_spacing,
+            )
+            .await,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn with_target(
+        wgpu_resources: crate::wgpu::RendererWgpuResources,
+        camera: &Camera,
+        color_uniform: &[f32; 4],
+        directional_lights: &[lights::DirectionalLight],
+        point_lights: &[lights::PointLight],
+        raytracer_accumulation: RaytracerAccumulation,
+        raytracer_settings: &RaytracerSettings,
+        raytracer_exposure: f32,
+        grid_fade_distance: f32,
+        grid_spacing: GridSpacing,
+    ) -> Self {
+        let crate::wgpu::RendererWgpuResources {
+            device,
+            queue,
+            adapter,
+            surface_config,
+            ..
+        } = &wgpu_resources;
 
         // Initialize vertex and index buffers
         let mesh = crate::mesh::CornellBox::new();
-        #[allow(unused_mut, unused_assignments)]
-        let mut vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertices Buffer"),
-            contents: bytemuck::cast_slice(&mesh.vertices),
+            contents: bytemuck::cast_slice(mesh.vertices),
             usage: wgpu::BufferUsages::VERTEX
                 | wgpu::BufferUsages::STORAGE
                 | wgpu::BufferUsages::COPY_DST,
         });
-        #[allow(unused_mut, unused_assignments)]
-        let mut index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Indices Buffer"),
-            contents: bytemuck::cast_slice(&mesh.indices),
+            contents: bytemuck::cast_slice(mesh.indices),
             usage: wgpu::BufferUsages::INDEX
                 | wgpu::BufferUsages::STORAGE
                 | wgpu::BufferUsages::COPY_DST,
         });
-        #[allow(unused_mut, unused_assignments)]
-        let mut num_indices = mesh.indices.len() as u32;
+        let num_indices = mesh.indices.len() as u32;
+        let mesh_bounds = crate::mesh::vertex_bounds(mesh.vertices);
+        let (wireframe_index_buffer, num_wireframe_indices) =
+            create_wireframe_index_buffer(device, mesh.indices);
 
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let mesh = crate::mesh::PlyMesh::new("assets/cornell-box.ply");
-            vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Vertices Buffer"),
-                contents: bytemuck::cast_slice(&mesh.vertices),
-                usage: wgpu::BufferUsages::VERTEX
-                    | wgpu::BufferUsages::STORAGE
-                    | wgpu::BufferUsages::COPY_DST,
-            });
-            index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Indices Buffer"),
-                contents: bytemuck::cast_slice(&mesh.indices),
-                usage: wgpu::BufferUsages::INDEX
-                    | wgpu::BufferUsages::STORAGE
-                    | wgpu::BufferUsages::COPY_DST,
-            });
-            num_indices = mesh.indices.len() as u32;
-        }
+        // Both renderers shade with the same lights
+        let (directional_lights_buffer, directional_light_count_uniform_buffer) =
+            lights::create_directional_light_buffers(device, directional_lights);
+        let (point_lights_buffer, point_light_count_uniform_buffer) =
+            lights::create_point_light_buffers(device, point_lights);
 
         let (
             rasterizer_camera_view_proj_uniform,
             rasterizer_color_uniform_buffer,
-            rasterizer_sun_direction_uniform_buffer,
             rasterizer_bind_group,
+            rasterizer_shader,
+            rasterizer_render_pipeline_layout,
             rasterizer_render_pipeline,
+            rasterizer_wireframe_render_pipeline,
         ) = initialize_rasterizer(
-            &camera,
-            &color_uniform,
-            &sun_light.direction,
-            &device,
-            &surface,
-            &adapter,
+            camera,
+            RasterizerCulling::default(),
+            color_uniform,
+            &directional_lights_buffer,
+            &directional_light_count_uniform_buffer,
+            &point_lights_buffer,
+            &point_light_count_uniform_buffer,
+            device,
+            surface_config.format,
         );
 
         let rasterizer_depth_texture = crate::wgpu::Texture::create_depth_texture(
-            &device,
-            &surface_config,
+            device,
+            surface_config,
             "rasterizer_depth_texture",
         );
 
-        let (raytracer_result_texture, raytracer_result_texture_view) =
-            create_raytracer_result_texture(&device, window_size.width, window_size.height);
-
-        let (
-            raytracer_render_bind_group_layout,
-            raytracer_render_bind_group,
-            raytracer_render_pipeline,
-            raytracer_frame_count_uniform_buffer,
-            raytracer_vertex_stride_uniform_buffer,
-            raytracer_vertex_color_offset_uniform_buffer,
-            raytracer_vertex_normal_offset_uniform_buffer,
-            raytracer_camera_to_world_uniform_buffer,
-            raytracer_camera_inverse_projection_uniform_buffer,
-            raytracer_sun_direction_uniform_buffer,
-            raytracer_compute_bind_group_layout,
-            raytracer_compute_bind_group,
-            raytracer_compute_pipeline,
-        ) = initialize_raytracer(
-            0,
-            &vertex_buffer,
-            &index_buffer,
-            &camera,
-            &sun_light.direction,
-            &raytracer_result_texture_view,
-            &device,
-            &surface,
-            &adapter,
+        let (grid_uniform_buffer, grid_bind_group, grid_render_pipeline) = initialize_grid(
+            camera,
+            grid_fade_distance,
+            grid_spacing,
+            device,
+            surface_config.format,
         );
 
+        // Devices that can't bind f16 storage textures read-write fall back to the compatible format,
+        // which only has to be checked again in that case
+        let downlevel_capabilities = adapter.get_downlevel_capabilities();
+        let (raytracer_accumulation, raytracer_support) = match check_raytracer_support(
+            &downlevel_capabilities,
+            result_format_features(raytracer_accumulation, adapter, device),
+        ) {
+            Err(reason) if raytracer_accumulation != RaytracerAccumulation::Compatible => {
+                log::info!(
+                    "Falling back from {raytracer_accumulation:?} raytracer accumulation: {reason}"
+                );
+                (
+                    RaytracerAccumulation::Compatible,
+                    check_raytracer_support(
+                        &downlevel_capabilities,
+                        result_format_features(RaytracerAccumulation::Compatible, adapter, device),
+                    ),
+                )
+            }
+            raytracer_support => (raytracer_accumulation, raytracer_support),
+        };
+
+        // wgpu panics on validation errors nobody captures, so a raytracer shader or pipeline
+        // that fails to validate disables the raytracer instead of the whole app
+        let raytracer = match raytracer_support {
+            Ok(()) => {
+                let (raytracer, validation_error) = capture_validation_error(device, || {
+                    create_raytracer(
+                        raytracer_accumulation,
+                        surface_config.width,
+                        surface_config.height,
+                        camera,
+                        raytracer_settings,
+                        raytracer_exposure,
+                        &vertex_buffer,
+                        &index_buffer,
+                        &directional_lights_buffer,
+                        &directional_light_count_uniform_buffer,
+                        crate::wgpu::Texture::create_environment_texture(
+                            device,
+                            queue,
+                            &EnvironmentMap::black(),
+                            "raytracer_environment_texture",
+                        ),
+                        device,
+                        surface_config.format,
+                    )
+                });
+
+                match validation_error.await {
+                    Some(error) => Err(error.to_string()),
+                    None => Ok(raytracer),
+                }
+            }
+            Err(reason) => Err(reason.to_string()),
+        };
+        if let Err(reason) = &raytracer {
+            log::warn!("Raytracer disabled: {reason}");
+        }
+
+        let gpu_timer = GpuTimer::new(device, queue);
+
+        let device_lost_message = Arc::new(std::sync::Mutex::new(None));
+        device.set_device_lost_callback({
+            let device_lost_message = device_lost_message.clone();
+            move |reason, message| {
+                // The callback also runs when the renderer drops the device, which isn't a loss
+                if reason != wgpu::DeviceLostReason::Dropped {
+                    *device_lost_message.lock().unwrap() = Some(format!("{reason:?}: {message}"));
+                }
+            }
+        });
+
         Self {
-            wgpu: crate::wgpu::RendererWgpuResources {
-                instance,
-                surface,
-                adapter,
-                device,
-                queue,
-                surface_config,
-            },
-            egui: crate::egui::RendererEguiResources {
-                renderer: egui_renderer,
-                state: egui_state,
-            },
+            wgpu: wgpu_resources,
+            gpu_timer,
+            #[cfg(target_arch = "wasm32")]
+            raytracer_validation_error: Default::default(),
+            device_lost_message,
             vertex_buffer,
             index_buffer,
             num_indices,
+            wireframe_index_buffer,
+            num_wireframe_indices,
+            mesh_bounds,
+            directional_lights_buffer,
+            directional_light_count_uniform_buffer,
+            point_lights_buffer,
+            point_light_count_uniform_buffer,
             rasterizer: rasterizer::Rasterizer {
                 depth_texture: rasterizer_depth_texture,
                 camera_view_proj_uniform: rasterizer_camera_view_proj_uniform,
                 color_uniform_buffer: rasterizer_color_uniform_buffer,
-                sun_direction_uniform_buffer: rasterizer_sun_direction_uniform_buffer,
                 bind_group: rasterizer_bind_group,
+                shader: rasterizer_shader,
+                render_pipeline_layout: rasterizer_render_pipeline_layout,
+                culling: RasterizerCulling::default(),
                 render_pipeline: rasterizer_render_pipeline,
+                wireframe_render_pipeline: rasterizer_wireframe_render_pipeline,
             },
-            raytracer: raytracer::Raytracer {
-                result_texture: raytracer_result_texture,
-                result_texture_view: raytracer_result_texture_view,
-                render_bind_group_layout: raytracer_render_bind_group_layout,
-                render_bind_group: raytracer_render_bind_group,
-                render_pipeline: raytracer_render_pipeline,
-                frame_count_uniform_buffer: raytracer_frame_count_uniform_buffer,
-                vertex_stride_uniform_buffer: raytracer_vertex_stride_uniform_buffer,
-                vertex_color_offset_uniform_buffer: raytracer_vertex_color_offset_uniform_buffer,
-                vertex_normal_offset_uniform_buffer: raytracer_vertex_normal_offset_uniform_buffer,
-                camera_to_world_uniform_buffer: raytracer_camera_to_world_uniform_buffer,
-                camera_inverse_projection_uniform_buffer:
-                    raytracer_camera_inverse_projection_uniform_buffer,
-                sun_direction_uniform_buffer: raytracer_sun_direction_uniform_buffer,
-                compute_bind_group_layout: raytracer_compute_bind_group_layout,
-                compute_bind_group: raytracer_compute_bind_group,
-                compute_pipeline: raytracer_compute_pipeline,
+            grid: grid::Grid {
+                uniform_buffer: grid_uniform_buffer,
+                bind_group: grid_bind_group,
+                render_pipeline: grid_render_pipeline,
             },
+            raytracer,
+        }
+    }
+
+    /// Reconfigures the surface with the present mode, falling back to `Fifo` if it isn't supported.
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        // Headless frames aren't presented
+        let Some(surface) = self.wgpu.surface() else {
+            return;
+        };
+
+        let supported_present_modes = surface.get_capabilities(&self.wgpu.adapter).present_modes;
+        self.wgpu.surface_config.present_mode =
+            crate::wgpu::supported_present_mode(present_mode, &supported_present_modes);
+        self.wgpu.configure_target();
+    }
+
+    pub fn set_rasterizer_culling(&mut self, culling: RasterizerCulling) {
+        self.rasterizer.render_pipeline = create_rasterizer_render_pipeline(
+            "Rasterizer Render Pipeline",
+            culling.primitive_state(),
+            &self.rasterizer.shader,
+            &self.rasterizer.render_pipeline_layout,
+            self.wgpu.surface_config.format,
+            &self.wgpu.device,
+        );
+        self.rasterizer.culling = culling;
+    }
+
+    /// Replaces the vertex and index buffers both renderers draw from with the mesh's.
+    pub fn set_mesh(&mut self, mesh: &crate::mesh::Mesh) {
+        self.vertex_buffer =
+            self.wgpu
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Vertices Buffer"),
+                    contents: bytemuck::cast_slice(&mesh.vertices),
+                    usage: wgpu::BufferUsages::VERTEX
+                        | wgpu::BufferUsages::STORAGE
+                        | wgpu::BufferUsages::COPY_DST,
+                });
+        self.index_buffer =
+            self.wgpu
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Indices Buffer"),
+                    contents: bytemuck::cast_slice(&mesh.indices),
+                    usage: wgpu::BufferUsages::INDEX
+                        | wgpu::BufferUsages::STORAGE
+                        | wgpu::BufferUsages::COPY_DST,
+                });
+        self.num_indices = mesh.indices.len() as u32;
+        (self.wireframe_index_buffer, self.num_wireframe_indices) =
+            create_wireframe_index_buffer(&self.wgpu.device, &mesh.indices);
+        self.mesh_bounds = mesh.bounds();
+
+        self.recreate_raytracer_bind_groups();
+    }
+
+    // Needed whenever a resource bound by the raytracer is replaced
+    pub fn recreate_raytracer_bind_groups(&mut self) {
+        let Ok(raytracer) = &mut self.raytracer else {
+            return;
+        };
+
+        let (raytracer_render_bind_group, raytracer_compute_bind_group) =
+            create_raytracer_bind_groups(
+                &raytracer.result_texture_view,
+                &self.wgpu.device,
+                &raytracer.render_bind_group_layout,
+                &raytracer.compute_bind_group_layout,
+                &self.vertex_buffer,
+                &self.index_buffer,
+                &raytracer.frame_count_uniform_buffer,
+                &raytracer.vertex_stride_uniform_buffer,
+                &raytracer.vertex_color_offset_uniform_buffer,
+                &raytracer.vertex_normal_offset_uniform_buffer,
+                &raytracer.camera_to_world_uniform_buffer,
+                &raytracer.camera_inverse_projection_uniform_buffer,
+                &raytracer.camera_lens_uniform_buffer,
+                &self.directional_lights_buffer,
+                &self.directional_light_count_uniform_buffer,
+                &raytracer.environment_texture,
+                &raytracer.settings_uniform_buffer,
+                &raytracer.tonemap_uniform_buffer,
+            );
+        raytracer.render_bind_group = raytracer_render_bind_group;
+        raytracer.compute_bind_group = raytracer_compute_bind_group;
+
+        let [denoiser_texture_view_a, denoiser_texture_view_b] = &raytracer.denoiser.texture_views;
+        let (denoise_bind_groups, denoiser_render_bind_group) = create_denoiser_bind_groups(
+            &raytracer.result_texture_view,
+            [denoiser_texture_view_a, denoiser_texture_view_b],
+            &self.wgpu.device,
+            &raytracer.denoiser.bind_group_layout,
+            &raytracer.render_bind_group_layout,
+            &raytracer.denoiser.pass_uniform_buffers,
+            &raytracer.tonemap_uniform_buffer,
+        );
+        raytracer.denoiser.bind_groups = denoise_bind_groups;
+        raytracer.denoiser.render_bind_group = denoiser_render_bind_group;
+    }
+
+    /// Fails, keeping the current environment map, if the map is too large for a texture on this device.
+    pub fn set_environment_map(&mut self, environment_map: &EnvironmentMap) -> Result<(), String> {
+        let max_size = self.wgpu.device.limits().max_texture_dimension_2d;
+        if environment_map.width > max_size || environment_map.height > max_size {
+            return Err(format!(
+                "{}x{} is larger than the {max_size}x{max_size} textures this device supports",
+                environment_map.width, environment_map.height
+            ));
         }
+
+        let Ok(raytracer) = &mut self.raytracer else {
+            return Ok(());
+        };
+
+        raytracer.environment_texture = crate::wgpu::Texture::create_environment_texture(
+            &self.wgpu.device,
+            &self.wgpu.queue,
+            environment_map,
+            "raytracer_environment_texture",
+        );
+
+        self.recreate_raytracer_bind_groups();
+
+        Ok(())
+    }
+
+    /// Recreates the raytracer around a result texture of the given format, keeping the
+    /// current environment map. Leaves the raytracer untouched if the format isn't supported,
+    /// and disables it with the validation error if its shaders or pipelines are invalid.
+    pub fn set_raytracer_accumulation(
+        &mut self,
+        accumulation: RaytracerAccumulation,
+        camera: &Camera,
+        settings: &RaytracerSettings,
+        exposure: f32,
+    ) -> Result<(), String> {
+        check_raytracer_support(
+            &self.wgpu.adapter.get_downlevel_capabilities(),
+            result_format_features(accumulation, &self.wgpu.adapter, &self.wgpu.device),
+        )?;
+
+        let environment_texture = match std::mem::replace(
+            &mut self.raytracer,
+            Err("The raytracer is being recreated".to_string()),
+        ) {
+            Ok(raytracer) => raytracer.environment_texture,
+            Err(_) => crate::wgpu::Texture::create_environment_texture(
+                &self.wgpu.device,
+                &self.wgpu.queue,
+                &EnvironmentMap::black(),
+                "raytracer_environment_texture",
+            ),
+        };
+
+        let (raytracer, validation_error) = capture_validation_error(&self.wgpu.device, || {
+            create_raytracer(
+                accumulation,
+                self.wgpu.surface_config.width,
+                self.wgpu.surface_config.height,
+                camera,
+                settings,
+                exposure,
+                &self.vertex_buffer,
+                &self.index_buffer,
+                &self.directional_lights_buffer,
+                &self.directional_light_count_uniform_buffer,
+                environment_texture,
+                &self.wgpu.device,
+                self.wgpu.surface_config.format,
+            )
+        });
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(error) = pollster::block_on(validation_error) {
+            self.raytracer = Err(error.to_string());
+            return Err(error.to_string());
+        }
+        // The browser can't be blocked on, so the raytracer is disabled later if it fails to validate
+        #[cfg(target_arch = "wasm32")]
+        {
+            let raytracer_validation_error = self.raytracer_validation_error.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Some(error) = validation_error.await {
+                    *raytracer_validation_error.borrow_mut() = Some(error.to_string());
+                }
+            });
+        }
+        self.raytracer = Ok(raytracer);
+
+        Ok(())
+    }
+
+    /// Configures the surface again with its current configuration, for when it goes stale without the window
+    /// changing size. Unlike resizing, the raytracer's textures and accumulated frames are kept.
+    pub fn reconfigure_surface(&mut self) {
+        self.wgpu.configure_target();
+    }
+
+    /// Returns why the device was lost if it has been, nothing can be rendered with it afterwards.
+    pub fn device_lost_message(&self) -> Option<String> {
+        self.device_lost_message.lock().unwrap().clone()
+    }
+
+    /// Disables the raytracer if WebGPU has since reported that recreating it failed to validate,
+    /// and returns why.
+    #[cfg(target_arch = "wasm32")]
+    pub fn take_raytracer_validation_error(&mut self) -> Option<String> {
+        let error = self.raytracer_validation_error.borrow_mut().take()?;
+        self.raytracer = Err(error.clone());
+
+        Some(error)
+    }
+}
+
+/// Runs `create` in a validation error scope, so wgpu returns its errors instead of panicking.
+/// The future resolves to the first error once the device has validated everything `create` did.
+/// It's boxed so it doesn't borrow what `create` captured, and can be awaited after it's gone.
+fn capture_validation_error<T>(
+    device: &wgpu::Device,
+    create: impl FnOnce() -> T,
+) -> (
+    T,
+    std::pin::Pin<Box<dyn std::future::Future<Output = Option<wgpu::Error>>>>,
+) {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+
+    (value, Box::pin(device.pop_error_scope()))
+}
+
+async fn request_adapter(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface<'_>>,
+    wgpu_options: &WgpuOptions,
+) -> Option<wgpu::Adapter> {
+    instance
+        .request_adapter(&wgpu_options.request_adapter_options(compatible_surface))
+        .await
+}
+
+async fn request_device(
+    adapter: &wgpu::Adapter,
+    wgpu_options: &WgpuOptions,
+) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+    let required_limits = if wgpu::Limits::default().check_limits(&adapter.limits()) {
+        wgpu::Limits::default()
+    } else {
+        wgpu::Limits::downlevel_defaults()
+    };
+
+    // Create the logical device and command queue
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("Device"),
+                // Only request the features if they're there, downlevel adapters fall back to the guaranteed format features.
+                // Without timestamp queries the GPU pass timings aren't shown.
+                required_features: wgpu_options.required_features
+                    | adapter.features()
+                        & (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES // This can be removed when wgpu is upgraded to the next version.
+                            | wgpu::Features::TIMESTAMP_QUERY),
+                // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the swapchain.
+                required_limits: required_limits.using_resolution(adapter.limits()),
+                memory_hints: wgpu::MemoryHints::Performance,
+            },
+            None,
+        )
+        .await
+}
+
+fn create_wireframe_index_buffer(device: &wgpu::Device, indices: &[u32]) -> (wgpu::Buffer, u32) {
+    let edge_indices = crate::mesh::edge_indices(indices);
+    let wireframe_index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Wireframe Indices Buffer"),
+        contents: bytemuck::cast_slice(&edge_indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+
+    (wireframe_index_buffer, edge_indices.len() as u32)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_raytracer(
+    accumulation: RaytracerAccumulation,
+    width: u32,
+    height: u32,
+    camera: &Camera,
+    settings: &RaytracerSettings,
+    exposure: f32,
+    vertex_buffer: &wgpu::Buffer,
+    index_buffer: &wgpu::Buffer,
+    directional_lights_buffer: &wgpu::Buffer,
+    directional_light_count_uniform_buffer: &wgpu::Buffer,
+    environment_texture: crate::wgpu::Texture,
+    device: &wgpu::Device,
+    surface_format: wgpu::TextureFormat,
+) -> raytracer::Raytracer {
+    let (raytracer_result_texture, raytracer_result_texture_view) =
+        create_raytracer_result_texture(device, width, height, accumulation);
+
+    let (
+        raytracer_render_bind_group_layout,
+        raytracer_render_bind_group,
+        raytracer_render_pipeline,
+        raytracer_frame_count_uniform_buffer,
+        raytracer_vertex_stride_uniform_buffer,
+        raytracer_vertex_color_offset_uniform_buffer,
+        raytracer_vertex_normal_offset_uniform_buffer,
+        raytracer_camera_to_world_uniform_buffer,
+        raytracer_camera_inverse_projection_uniform_buffer,
+        raytracer_camera_lens_uniform_buffer,
+        raytracer_settings_uniform_buffer,
+        raytracer_tonemap_uniform_buffer,
+        raytracer_compute_bind_group_layout,
+        raytracer_compute_bind_group,
+        raytracer_compute_pipeline,
+    ) = initialize_raytracer(
+        accumulation,
+        0,
+        vertex_buffer,
+        index_buffer,
+        camera,
+        settings,
+        exposure,
+        directional_lights_buffer,
+        directional_light_count_uniform_buffer,
+        &environment_texture,
+        &raytracer_result_texture_view,
+        device,
+        surface_format,
+    );
+
+    let [(denoiser_texture_a, denoiser_texture_view_a), (denoiser_texture_b, denoiser_texture_view_b)] =
+        create_denoiser_textures(device, width, height, accumulation);
+
+    let (
+        denoiser_pass_uniform_buffers,
+        denoise_bind_group_layout,
+        denoise_bind_groups,
+        denoiser_render_bind_group,
+        denoise_pipeline,
+    ) = initialize_denoiser(
+        accumulation,
+        &raytracer_result_texture_view,
+        [&denoiser_texture_view_a, &denoiser_texture_view_b],
+        &raytracer_render_bind_group_layout,
+        &raytracer_tonemap_uniform_buffer,
+        device,
+    );
+
+    raytracer::Raytracer {
+        accumulation,
+        result_texture: raytracer_result_texture,
+        result_texture_view: raytracer_result_texture_view,
+        environment_texture,
+        render_bind_group_layout: raytracer_render_bind_group_layout,
+        render_bind_group: raytracer_render_bind_group,
+        render_pipeline: raytracer_render_pipeline,
+        frame_count_uniform_buffer: raytracer_frame_count_uniform_buffer,
+        vertex_stride_uniform_buffer: raytracer_vertex_stride_uniform_buffer,
+        vertex_color_offset_uniform_buffer: raytracer_vertex_color_offset_uniform_buffer,
+        vertex_normal_offset_uniform_buffer: raytracer_vertex_normal_offset_uniform_buffer,
+        camera_to_world_uniform_buffer: raytracer_camera_to_world_uniform_buffer,
+        camera_inverse_projection_uniform_buffer:
+            raytracer_camera_inverse_projection_uniform_buffer,
+        camera_lens_uniform_buffer: raytracer_camera_lens_uniform_buffer,
+        settings_uniform_buffer: raytracer_settings_uniform_buffer,
+        tonemap_uniform_buffer: raytracer_tonemap_uniform_buffer,
+        compute_bind_group_layout: raytracer_compute_bind_group_layout,
+        compute_bind_group: raytracer_compute_bind_group,
+        compute_pipeline: raytracer_compute_pipeline,
+        denoiser: Denoiser {
+            textures: [denoiser_texture_a, denoiser_texture_b],
+            texture_views: [denoiser_texture_view_a, denoiser_texture_view_b],
+            pass_uniform_buffers: denoiser_pass_uniform_buffers,
+            bind_group_layout: denoise_bind_group_layout,
+            bind_groups: denoise_bind_groups,
+            render_bind_group: denoiser_render_bind_group,
+            compute_pipeline: denoise_pipeline,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        environment::EnvironmentMap,
+        mesh::Mesh,
+        raytracer::{RaytracerAccumulation, RaytracerSettings},
+        renderer::RenderStats,
+        test_utils,
+        wgpu::update_buffer,
+    };
+
+    #[test]
+    fn headless_rasterizer_frame_is_read_back() {
+        let Some(renderer) = test_utils::headless_renderer(
+            64,
+            48,
+            RaytracerAccumulation::default(),
+            &RaytracerSettings::default(),
+        ) else {
+            return;
+        };
+
+        let frame = test_utils::render_rasterizer_frame(
+            &renderer,
+            false,
+            false,
+            false,
+            wgpu::Color::BLACK,
+            &mut RenderStats::default(),
+        );
+
+        assert_eq!(frame.dimensions(), (64, 48));
+        // The Cornell box fills most of the frame, so it can't all be the clear color
+        assert!(frame.pixels().any(|pixel| pixel.0 != [0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn culled_mesh_leaves_the_clear_color() {
+        let Some(renderer) = test_utils::headless_renderer(
+            16,
+            16,
+            RaytracerAccumulation::default(),
+            &RaytracerSettings::default(),
+        ) else {
+            return;
+        };
+        // Zero and one are the same in sRGB and linear, so the texture's encoding doesn't matter
+        let clear_color = wgpu::Color {
+            r: 1.0,
+            g: 0.0,
+            b: 1.0,
+            a: 1.0,
+        };
+
+        let frame = test_utils::render_rasterizer_frame(
+            &renderer,
+            true,
+            false,
+            false,
+            clear_color,
+            &mut RenderStats::default(),
+        );
+
+        assert!(frame.pixels().all(|pixel| pixel.0 == [255, 0, 255, 255]));
+    }
+
+    #[test]
+    fn rasterizer_counts_mesh_and_grid_draws() {
+        let Some(renderer) = test_utils::headless_renderer(
+            16,
+            16,
+            RaytracerAccumulation::default(),
+            &RaytracerSettings::default(),
+        ) else {
+            return;
+        };
+
+        for (is_mesh_culled, is_grid_enabled, draw_calls) in [
+            (false, true, 2),
+            (false, false, 1),
+            (true, true, 1),
+            (true, false, 0),
+        ] {
+            let mut render_stats = RenderStats::default();
+            test_utils::render_rasterizer_frame(
+                &renderer,
+                is_mesh_culled,
+                false,
+                is_grid_enabled,
+                wgpu::Color::BLACK,
+                &mut render_stats,
+            );
+
+            assert_eq!(render_stats.draw_calls, draw_calls);
+            assert_eq!(render_stats.instances, draw_calls);
+            assert_eq!(render_stats.compute_workgroups, 0);
+        }
+    }
+
+    #[test]
+    fn f16_raytracer_builds_its_texture_and_bind_groups() {
+        let Some(mut renderer) = test_utils::headless_renderer(
+            16,
+            16,
+            RaytracerAccumulation::F16,
+            &RaytracerSettings::default(),
+        ) else {
+            return;
+        };
+        let raytracer = renderer.raytracer.as_ref().unwrap();
+        // Adapters without read-write f16 storage fall back to the compatible format
+        if raytracer.accumulation != RaytracerAccumulation::F16 {
+            return;
+        }
+        assert_eq!(
+            raytracer.result_texture.format(),
+            wgpu::TextureFormat::Rgba16Float
+        );
+
+        renderer
+            .wgpu
+            .device
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+        renderer.recreate_raytracer_bind_groups();
+        let error = pollster::block_on(renderer.wgpu.device.pop_error_scope());
+
+        assert!(error.is_none(), "{error:?}");
+    }
+
+    #[test]
+    fn raytracer_settings_uniform_holds_the_max_bounces() {
+        let mut settings = RaytracerSettings::default();
+        settings.max_bounces = 5;
+        let Some(renderer) =
+            test_utils::headless_renderer(16, 16, RaytracerAccumulation::default(), &settings)
+        else {
+            return;
+        };
+        let raytracer = renderer.raytracer.as_ref().unwrap();
+        let read_settings = || {
+            let contents = test_utils::read_buffer(
+                &renderer.wgpu.device,
+                &renderer.wgpu.queue,
+                &raytracer.settings_uniform_buffer,
+            );
+            *bytemuck::from_bytes::<RaytracerSettings>(&contents)
+        };
+        assert_eq!(read_settings().max_bounces, 5);
+
+        // Like the Max Bounces slider does
+        settings.max_bounces = 12;
+        update_buffer(
+            &renderer.wgpu.queue,
+            &raytracer.settings_uniform_buffer,
+            &[settings],
+        );
+
+        assert_eq!(read_settings(), settings);
+    }
+
+    #[test]
+    fn replaced_mesh_is_drawn_and_rebound() {
+        let Some(mut renderer) = test_utils::headless_renderer(
+            32,
+            32,
+            RaytracerAccumulation::default(),
+            &RaytracerSettings::default(),
+        ) else {
+            return;
+        };
+        let mesh = Mesh::parse_obj(include_str!("../test_data/cube.obj")).unwrap();
+
+        renderer
+            .wgpu
+            .device
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+        renderer.set_mesh(&mesh);
+        let frame = test_utils::render_rasterizer_frame(
+            &renderer,
+            false,
+            false,
+            false,
+            wgpu::Color::BLACK,
+            &mut RenderStats::default(),
+        );
+        let error = pollster::block_on(renderer.wgpu.device.pop_error_scope());
+
+        assert!(error.is_none(), "{error:?}");
+        assert_eq!(renderer.num_indices, 36);
+        // The faces have their own vertices, so each one has four sides and a diagonal
+        assert_eq!(renderer.num_wireframe_indices, 6 * 5 * 2);
+        assert_eq!(
+            renderer.mesh_bounds,
+            (glam::Vec3::splat(-0.5), glam::Vec3::splat(0.5))
+        );
+        // The cube is in front of the camera, so it covers the middle of the frame
+        assert_ne!(frame.get_pixel(16, 16).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn oversized_environment_map_is_rejected() {
+        let Some(mut renderer) = test_utils::headless_renderer(
+            16,
+            16,
+            RaytracerAccumulation::default(),
+            &RaytracerSettings::default(),
+        ) else {
+            return;
+        };
+        let width = renderer.wgpu.device.limits().max_texture_dimension_2d + 1;
+
+        let error = renderer
+            .set_environment_map(&EnvironmentMap {
+                width,
+                height: 1,
+                texels: vec![0.0; width as usize * 4],
+            })
+            .unwrap_err();
+
+        assert!(error.contains(&format!("{width}x1")), "{error}");
+        assert!(renderer
+            .set_environment_map(&EnvironmentMap::black())
+            .is_ok());
+    }
+
+    #[test]
+    fn validation_errors_are_captured_with_the_shader_label() {
+        let Some((_, device, _)) = test_utils::device(wgpu::Features::empty()) else {
+            return;
+        };
+
+        let (_, error) = super::capture_validation_error(&device, || {
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Broken Shader"),
+                source: wgpu::ShaderSource::Wgsl("fn main() { let x: f32 = true; }".into()),
+            })
+        });
+        let error = pollster::block_on(error).expect("The shader should fail to validate");
+
+        assert!(error.to_string().contains("Broken Shader"), "{error}");
+
+        let (_, error) = super::capture_validation_error(&device, || {
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Valid Shader"),
+                source: wgpu::ShaderSource::Wgsl("fn main() { let x: f32 = 1.0; }".into()),
+            })
+        });
+        assert!(pollster::block_on(error).is_none());
+    }
+
+    #[test]
+    fn destroyed_device_is_reported_lost() {
+        let Some(renderer) = test_utils::headless_renderer(
+            16,
+            16,
+            RaytracerAccumulation::default(),
+            &RaytracerSettings::default(),
+        ) else {
+            return;
+        };
+        assert_eq!(renderer.device_lost_message(), None);
+
+        renderer.wgpu.device.destroy();
+        renderer.wgpu.device.poll(wgpu::Maintain::Wait);
+
+        let message = renderer.device_lost_message().unwrap();
+        assert!(message.starts_with("Destroyed"), "{message}");
     }
 }
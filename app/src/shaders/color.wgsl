@@ -0,0 +1,18 @@
+// Included at the top of the shaders that write to the surface.
+// Colors are linear everywhere in the renderer. sRGB surfaces encode them on write,
+// for other surfaces this is replaced so `encode_output_color` encodes them by hand.
+const IS_SRGB_ENCODING_ENABLED: bool = false;
+
+fn linear_to_srgb(color: vec3f) -> vec3f {
+    let clamped_color = clamp(color, vec3f(0.0), vec3f(1.0));
+    let curve = 1.055 * pow(clamped_color, vec3f(1.0 / 2.4)) - 0.055;
+    return select(curve, clamped_color * 12.92, clamped_color <= vec3f(0.0031308));
+}
+
+fn encode_output_color(color: vec3f) -> vec3f {
+    if (IS_SRGB_ENCODING_ENABLED) {
+        return linear_to_srgb(color);
+    }
+
+    return color;
+}
@@ -0,0 +1,94 @@
+struct Grid {
+    view_proj: mat4x4f,
+    inverse_view_proj: mat4x4f,
+    camera_position: vec3f,
+    fade_distance: f32,
+    minor_spacing: f32,
+    major_spacing: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f, // Clip-space position
+    @location(0) ndc: vec2f,
+}
+
+struct FragmentOutput {
+    @builtin(frag_depth) depth: f32,
+    @location(0) color: vec4f,
+}
+
+@group(0) @binding(0)
+var<uniform> grid: Grid;
+
+var<private> full_screen_tri_positions: array<vec2f, 3> = array<vec2f, 3>(
+    vec2f(-1.0, -3.0),
+    vec2f(-1.0, 1.0),
+    vec2f(3.0, 1.0)
+);
+
+@vertex
+fn vs_main(@builtin(vertex_index) vi: u32) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = vec4f(full_screen_tri_positions[vi], 0.0, 1.0);
+    out.ndc = out.position.xy;
+
+    return out;
+}
+
+fn unproject(ndc: vec3f) -> vec3f {
+    let position = grid.inverse_view_proj * vec4f(ndc, 1.0);
+    return position.xyz / position.w;
+}
+
+// Returns 1.0 on a grid line and 0.0 between lines, antialiased over a pixel
+fn get_grid_line(coords: vec2f, spacing: f32) -> f32 {
+    let scaled_coords = coords / spacing;
+    let line_width = fwidth(scaled_coords);
+    let distance_to_line = abs(fract(scaled_coords - 0.5) - 0.5) / line_width;
+    return 1.0 - min(min(distance_to_line.x, distance_to_line.y), 1.0);
+}
+
+// Returns how close the coordinates are to each axis, x is the Z axis at x = 0 and y is the X axis at z = 0.
+// Unlike the grid lines, the axes stay the same width on screen whatever the spacing is.
+fn get_axis_lines(coords: vec2f) -> vec2f {
+    let line_width = fwidth(coords) * 1.5;
+    return 1.0 - min(abs(coords) / line_width, vec2f(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    // Intersect the view ray through the pixel with the y = 0 ground plane
+    let near = unproject(vec3f(in.ndc, 0.0));
+    let far = unproject(vec3f(in.ndc, 1.0));
+    let t = -near.y / (far.y - near.y);
+    let world_position = near + t * (far - near);
+
+    let clip_position = grid.view_proj * vec4f(world_position, 1.0);
+    let depth = clip_position.z / clip_position.w;
+
+    // Derivatives need uniform control flow, so the lines are found before discarding
+    let minor_line = get_grid_line(world_position.xz, grid.minor_spacing);
+    let major_line = get_grid_line(world_position.xz, grid.major_spacing);
+    let axis_lines = get_axis_lines(world_position.xz);
+
+    // Written as a negation so rays parallel to the plane, which give NaNs, are discarded too
+    if (!(t > 0.0 && depth >= 0.0 && depth <= 1.0)) {
+        discard;
+    }
+
+    let fade = 1.0 - clamp(distance(grid.camera_position, world_position) / grid.fade_distance, 0.0, 1.0);
+    var color = vec3f(0.6);
+    var alpha = max(minor_line * 0.3, major_line * 0.6);
+
+    // Highlight the X axis in red and the Z axis in blue
+    color = mix(color, vec3f(0.2, 0.4, 1.0), axis_lines.x);
+    alpha = max(alpha, axis_lines.x);
+    color = mix(color, vec3f(1.0, 0.2, 0.2), axis_lines.y);
+    alpha = max(alpha, axis_lines.y);
+
+    var out: FragmentOutput;
+    out.depth = depth;
+    out.color = vec4f(encode_output_color(color), alpha * fade);
+
+    return out;
+}
@@ -12,6 +12,14 @@ struct VertexOutput {
     @builtin(position) position: vec4f, // Clip space position
     @location(0) color: vec4f,
     @location(1) normal: vec3f,
+    @location(2) world_position: vec3f,
+}
+
+struct PointLight {
+    position: vec3f,
+    intensity: f32,
+    color: vec3f,
+    range: f32,
 }
 
 @group(0) @binding(0)
@@ -19,7 +27,13 @@ var<uniform> camera_uniform: Camera;
 @group(0) @binding(1)
 var<uniform> color_uniform: vec4f;
 @group(0) @binding(2)
-var<uniform> sun_direction: vec3f;
+var<storage, read> directional_lights: array<vec4f>; // xyz is the direction towards the light
+@group(0) @binding(3)
+var<uniform> directional_light_count: u32;
+@group(0) @binding(4)
+var<storage, read> point_lights: array<PointLight>;
+@group(0) @binding(5)
+var<uniform> point_light_count: u32;
 
 @vertex
 fn vs_main(model: VertexInput) -> VertexOutput {
@@ -28,16 +42,40 @@ fn vs_main(model: VertexInput) -> VertexOutput {
     out.position = camera_uniform.view_proj * model.position;
     out.color = model.color;
     out.normal = model.normal;
+    out.world_position = model.position.xyz;
 
     return out;
 }
 
+// Inverse-square falloff, windowed so that it smoothly reaches zero at the light's range
+fn get_point_light_attenuation(distance: f32, range: f32) -> f32 {
+    let distance_ratio = distance / range;
+    let window = saturate(1.0 - pow(distance_ratio, 4.0));
+
+    return window * window / max(distance * distance, 0.0001);
+}
+
 @fragment
 fn fs_main(vert_output: VertexOutput) -> @location(0) vec4f {
-    let direct_light: f32 = max(0.0, dot(vert_output.normal, sun_direction));
+    var direct_light: f32 = 0.0;
+    for (var i: u32 = 0u; i < directional_light_count; i = i + 1u) {
+        direct_light += max(0.0, dot(vert_output.normal, directional_lights[i].xyz));
+    }
+
+    var point_light = vec3f(0.0);
+    for (var i: u32 = 0u; i < point_light_count; i = i + 1u) {
+        let light = point_lights[i];
+        let to_light = light.position - vert_output.world_position;
+        let distance = length(to_light);
+        let attenuation = get_point_light_attenuation(distance, light.range);
+        let n_dot_l = max(0.0, dot(vert_output.normal, to_light / max(distance, 0.0001)));
+        point_light += light.color * light.intensity * attenuation * n_dot_l;
+    }
+
     let ambient_light: f32 = 0.05;
-    let total_light: f32 = direct_light + ambient_light;
-    let color = vert_output.color * vec4f(total_light, total_light, total_light, 1.0);
+    let total_light: vec3f = vec3f(direct_light + ambient_light) + point_light;
+    let color = vert_output.color * vec4f(total_light, 1.0);
 
-    return color_uniform * color;
+    let output_color = color_uniform * color;
+    return vec4f(encode_output_color(output_color.rgb), output_color.a);
 }
@@ -1,7 +1,10 @@
 const K_EPSILON: f32 = 1e-6;
 const FLT_MAX: f32 = 1e12;
-const MAX_BOUNCES: u32 = 8;
 const SUN_INTENSITY: f32 = 1.0;
+const PI: f32 = 3.14159265;
+const MAX_RADIANCE: f32 = 1.0; // Replaced to match the result texture format
+const RUSSIAN_ROULETTE_MIN_BOUNCES: u32 = 3u; // Paths always survive this many bounces
+const RUSSIAN_ROULETTE_MIN_PROBABILITY: f32 = 0.05;
 
 // TODO: Break up bind groups, see https://toji.dev/webgpu-best-practices/bind-groups.html
 
@@ -22,9 +25,31 @@ var<uniform> camera_to_world: mat4x4f;
 @group(0) @binding(7)
 var<uniform> camera_inverse_projection: mat4x4f;
 @group(0) @binding(8)
-var<uniform> sun_direction: vec3f;
+var<storage, read> directional_lights: array<vec4f>; // xyz is the direction towards the light
 @group(0) @binding(9)
+var<uniform> directional_light_count: u32;
+@group(0) @binding(10)
 var result: texture_storage_2d<rgba8unorm, read_write>;
+@group(0) @binding(11)
+var environment_texture: texture_2d<f32>; // Equirectangular, +Y is up
+@group(0) @binding(12)
+var environment_sampler: sampler;
+@group(0) @binding(13)
+var<uniform> settings: Settings;
+
+@group(0) @binding(14)
+var<uniform> camera_lens: CameraLens;
+
+struct CameraLens {
+    aperture: f32,
+    focus_distance: f32,
+}
+
+struct Settings {
+    max_bounces: u32,
+    background_color: vec3f,
+    is_background_color_enabled: u32,
+}
 
 struct Vertex {
     position: vec4f,
@@ -87,18 +112,29 @@ fn create_ray(origin: vec3f, direction: vec3f) -> Ray {
     return ray;
 }
 
-fn create_camera_ray(uv: vec2f) -> Ray {
-    // Transform the camera origin to world space
-    let origin: vec3f = (camera_to_world * vec4f(0.0, 0.0, 0.0, 1.0)).xyz;
-
-    // Invert the perspective projection of the view-space position
-    var direction = (camera_inverse_projection * vec4f(uv, 0.0, 1.0)).xyz;
+// `lens_sample` is a point in the unit disk, where on the lens the ray starts
+fn create_camera_ray(uv: vec2f, lens_sample: vec2f) -> Ray {
+    // Unproject the pixel on the near and far planes, this works for both
+    // perspective and orthographic projections
+    let near = camera_inverse_projection * vec4f(uv, -1.0, 1.0);
+    let far = camera_inverse_projection * vec4f(uv, 1.0, 1.0);
+    var near_position = near.xyz / near.w;
+    var direction = normalize(far.xyz / far.w - near_position);
+
+    // Start the ray somewhere on the lens and aim it at where the pinhole ray crosses the focal plane,
+    // so only points at the focus distance stay sharp. The camera looks down -Z.
+    if (camera_lens.aperture > 0.0) {
+        let focus_t = (camera_lens.focus_distance + near_position.z) / -direction.z;
+        let focus_position = near_position + direction * focus_t;
+        near_position += vec3f(lens_sample * camera_lens.aperture * 0.5, 0.0);
+        direction = normalize(focus_position - near_position);
+    }
 
-    // Transform the direction from camera to world space and normalize
-    direction = (camera_to_world * vec4f(direction, 0.0)).xyz;
-    direction = normalize(direction);
+    // Transform the ray from camera to world space
+    let origin = (camera_to_world * vec4f(near_position, 1.0)).xyz;
+    let world_direction = normalize((camera_to_world * vec4f(direction, 0.0)).xyz);
 
-    return create_ray(origin, direction);
+    return create_ray(origin, world_direction);
 }
 
 fn get_unnormalized_triangle_normal(triangle: Triangle) -> vec3f {
@@ -260,12 +296,34 @@ fn get_interpolated_color(hit_info: HitInfo) -> vec4f {
     return triangle.a.color * hit_info.u + triangle.b.color * hit_info.v + triangle.c.color * hit_info.w;
 }
 
+fn get_environment_color(direction: vec3f) -> vec3f {
+    if (settings.is_background_color_enabled != 0u) {
+        return settings.background_color;
+    }
+
+    let u = atan2(direction.z, direction.x) / (2.0 * PI) + 0.5;
+    let v = acos(clamp(direction.y, -1.0, 1.0)) / PI;
+
+    return textureSampleLevel(environment_texture, environment_sampler, vec2f(u, v), 0.0).rgb;
+}
+
 fn get_sky_color(ray: Ray) -> vec4f {
-    // The sky is black except for the sun
-    var sun_intensity = max(0.0, dot(sun_direction, ray.direction));
-    sun_intensity = pow(sun_intensity, 32.0);
+    // The sky is the environment map plus the suns
+    var sun_intensity = 0.0;
+    for (var i: u32 = 0u; i < directional_light_count; i = i + 1u) {
+        sun_intensity += pow(max(0.0, dot(directional_lights[i].xyz, ray.direction)), 32.0);
+    }
+
+    return vec4f(vec3f(sun_intensity) + get_environment_color(ray.direction), 1.0);
+}
+
+fn get_sun_intensity(direction: vec3f) -> f32 {
+    var sun_intensity = 0.0;
+    for (var i: u32 = 0u; i < directional_light_count; i = i + 1u) {
+        sun_intensity += max(0.0, dot(directional_lights[i].xyz, direction)) * SUN_INTENSITY;
+    }
 
-    return vec4f(sun_intensity, sun_intensity, sun_intensity, 1.0);
+    return sun_intensity;
 }
 
 fn trace_triangles(ray: Ray) -> HitInfo {
@@ -308,9 +366,43 @@ fn random_in_hemisphere(normal: vec3f, seed: vec3f) -> vec3f {
     return normalize(normal + random_dir);
 }
 
+fn random_in_unit_disk(seed: vec3f) -> vec2f {
+    let angle = 2.0 * PI * fract(sin(dot(seed, vec3f(12.9898, 78.233, 45.164))) * 43758.5453);
+    // The square root spreads the samples evenly over the disk's area instead of bunching them in the middle
+    let radius = sqrt(fract(sin(dot(seed, vec3f(63.7264, 10.873, 98.234))) * 43758.5453));
+
+    return radius * vec2f(cos(angle), sin(angle));
+}
+
+// Returns the index-th element of the Halton sequence in the given base, which is in [0, 1)
+fn halton(index: u32, base: u32) -> f32 {
+    var fraction = 1.0;
+    var result = 0.0;
+    var i = index;
+    while (i > 0u) {
+        fraction /= f32(base);
+        result += fraction * f32(i % base);
+        i /= base;
+    }
+
+    return result;
+}
+
+// Returns where in the pixel the primary ray goes through, from (0, 0) to (1, 1).
+// Unlike random offsets, the 2-3 Halton sequence spreads the frames evenly over the pixel.
+fn get_pixel_jitter(frame: u32) -> vec2f {
+    // The sequence starts at 0, skip it so the first frame isn't in the pixel's corner
+    return vec2f(halton(frame + 1u, 2u), halton(frame + 1u, 3u));
+}
+
 @compute
 @workgroup_size(8, 8, 1)
 fn main(@builtin(global_invocation_id) id: vec3u) {
+    // The dispatch is rounded up to whole workgroups, so some invocations fall outside the texture
+    if (any(id.xy >= textureDimensions(result).xy)) {
+        return;
+    }
+
     // Get the current pixel coordinates
     let coords = vec2i(i32(id.x), i32(id.y));
 
@@ -326,10 +418,12 @@ fn main(@builtin(global_invocation_id) id: vec3u) {
     // Then the coordinates will go from -1 to 1 down the Y-axis
     // We want to go from -1 to 1 up the Y-axis to match WebGPU's render coordinate system
     // Reference: https://github.com/gfx-rs/wgpu?tab=readme-ov-file#coordinate-systems
-    let uv = ((vec2f(id.xy) + 0.5) / dims * 2.0 - 1.0) * vec2f(1.0, -1.0);
+    // The ray goes through a different point in the pixel every frame, so accumulating also antialiases
+    let uv = ((vec2f(id.xy) + get_pixel_jitter(frame_count)) / dims * 2.0 - 1.0) * vec2f(1.0, -1.0);
 
     // Get a ray for the UVs
-    var ray: Ray = create_camera_ray(uv);
+    let lens_sample = random_in_unit_disk(vec3f(vec2f(id.xy), f32(frame_count)));
+    var ray: Ray = create_camera_ray(uv, lens_sample);
 
     // Load the previous frame's color
     let prev_color = textureLoad(result, coords);
@@ -337,9 +431,11 @@ fn main(@builtin(global_invocation_id) id: vec3u) {
     // Trace the ray against the triangles
     var ray_color: vec4f = vec4f(0.0);
     var ray_throughput: vec4f = vec4f(1.0);
+    // How much the paths that survived Russian roulette stand in for, it scales the light they reach
+    var roulette_weight = 1.0;
 
-    for (var bounce = 0u; bounce <= MAX_BOUNCES; bounce += 1u) {
-        if (bounce == MAX_BOUNCES) {
+    for (var bounce = 0u; bounce <= settings.max_bounces; bounce += 1u) {
+        if (bounce == settings.max_bounces) {
             // Set ray color as black if the ray has bounced too many times
             ray_color = vec4f(0.0);
             break;
@@ -352,6 +448,23 @@ fn main(@builtin(global_invocation_id) id: vec3u) {
             ray_color += tri_color * ray_throughput;
             ray_throughput *= tri_color;
 
+            // Past the first few bounces, end dark paths early with Russian roulette.
+            // The paths that survive carry the energy of the ones that didn't, so the image stays the same on average.
+            if (bounce >= RUSSIAN_ROULETTE_MIN_BOUNCES) {
+                let survival_probability = clamp(
+                    max(ray_throughput.r, max(ray_throughput.g, ray_throughput.b)),
+                    RUSSIAN_ROULETTE_MIN_PROBABILITY,
+                    1.0
+                );
+                let roulette_seed = hit_info.p + vec3f(f32(frame_count) * 0.1 + f32(bounce));
+                if (fract(sin(dot(roulette_seed, vec3f(39.3467, 11.135, 83.155))) * 43758.5453) > survival_probability) {
+                    // Terminated like a path that reached the bounce limit
+                    ray_color = vec4f(0.0);
+                    break;
+                }
+                roulette_weight /= survival_probability;
+            }
+
             // Use frame number in random seed for temporal variation
             ray = create_ray(
                 hit_info.p + hit_info.normal * 0.001, // Move the origin slightly to avoid self-intersection
@@ -362,15 +475,16 @@ fn main(@builtin(global_invocation_id) id: vec3u) {
                 // If ray misses all triangles, return the sky color
                 ray_color = get_sky_color(ray);
             } else {
-                let ray_sun_intensity = max(0.0, dot(sun_direction, ray.direction)) * SUN_INTENSITY;
-                ray_color *= ray_sun_intensity * ray_throughput;
+                let ray_sun_intensity = get_sun_intensity(ray.direction);
+                let ray_environment_color = vec4f(get_environment_color(ray.direction), 0.0);
+                ray_color *= (ray_sun_intensity + ray_environment_color) * ray_throughput * roulette_weight;
             }
 
             break;
         }
     }
 
-    ray_color = clamp(ray_color, vec4f(0.0), vec4f(1.0));
+    ray_color = clamp(ray_color, vec4f(0.0), vec4f(MAX_RADIANCE));
 
     // Blend with previous frame
     let blend_factor = 1.0 / pow(f32(frame_count + 1), 1.05);
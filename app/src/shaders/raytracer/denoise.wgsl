@@ -0,0 +1,50 @@
+// One pass of an edge-avoiding à-trous wavelet filter, see https://jo.dreggn.org/home/2010_atrous.pdf
+// Each pass blurs with a 5x5 B3-spline kernel whose taps are `step_width` pixels apart,
+// weighting every tap by how close its color is to the center so edges stay sharp.
+
+struct Pass {
+    step_width: u32,
+    color_phi: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_storage_2d<rgba8unorm, read>;
+@group(0) @binding(1)
+var destination: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(2)
+var<uniform> pass_info: Pass;
+
+var<private> kernel: array<f32, 5> = array<f32, 5>(1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3u) {
+    let dims = vec2i(textureDimensions(source).xy);
+    let coords = vec2i(id.xy);
+
+    // The dispatch is rounded up to whole workgroups, so some invocations fall outside the texture
+    if (any(coords >= dims)) {
+        return;
+    }
+
+    let center_color = textureLoad(source, coords);
+
+    var color_sum = vec4f(0.0);
+    var weight_sum = 0.0;
+
+    for (var y = -2; y <= 2; y += 1) {
+        for (var x = -2; x <= 2; x += 1) {
+            let sample_coords = clamp(coords + vec2i(x, y) * i32(pass_info.step_width), vec2i(0), dims - 1);
+            let sample_color = textureLoad(source, sample_coords);
+
+            let color_difference = center_color.rgb - sample_color.rgb;
+            let color_weight = exp(-dot(color_difference, color_difference) / pass_info.color_phi);
+            let weight = kernel[x + 2] * kernel[y + 2] * color_weight;
+
+            color_sum += sample_color * weight;
+            weight_sum += weight;
+        }
+    }
+
+    // The center tap always has a weight above zero, so this can't divide by zero
+    textureStore(destination, coords, color_sum / weight_sum);
+}
@@ -1,5 +1,12 @@
+struct Tonemap {
+    exposure: f32,
+    is_tonemapping_enabled: u32,
+}
+
 @group(0) @binding(0)
 var result: texture_storage_2d<rgba8unorm, read>;
+@group(0) @binding(1)
+var<uniform> tonemap: Tonemap;
 
 struct VertexOutput {
     @builtin(position) position: vec4f, // Clip-space position
@@ -22,9 +29,20 @@ fn vs_main(@builtin(vertex_index) vi: u32) -> VertexOutput {
     return out;
 }
 
+// Krzysztof Narkowicz's fit of the ACES filmic curve, see https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/
+fn aces_filmic(color: vec3f) -> vec3f {
+    return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), vec3f(0.0), vec3f(1.0));
+}
+
 @fragment
 fn fs_main(vert_output: VertexOutput) -> @location(0) vec4f {
     let coord: vec2f = floor(vert_output.uv * vec2f(textureDimensions(result).xy));
+    let radiance = textureLoad(result, vec2i(coord));
+
+    var color = radiance.rgb * tonemap.exposure;
+    if (tonemap.is_tonemapping_enabled != 0u) {
+        color = aces_filmic(color);
+    }
 
-    return textureLoad(result, vec2i(coord));
+    return vec4f(encode_output_color(color), clamp(radiance.a, 0.0, 1.0));
 }
@@ -0,0 +1,181 @@
+//! Helpers for the tests that need a GPU. They panic when there's no adapter to run on, so a machine
+//! without one can't pass the suite without checking anything. Setting `WGPU_TESTS_ALLOW_NO_ADAPTER`
+//! makes them return `None` instead, and the tests that use them then return early.
+
+use crate::{
+    camera::Camera,
+    capture::FrameCapture,
+    grid::GridSpacing,
+    lights::DirectionalLight,
+    mesh::Mesh,
+    rasterizer::render_rasterizer,
+    raytracer::{RaytracerAccumulation, RaytracerSettings},
+    renderer::{RenderStats, Renderer},
+    wgpu::{RenderTarget, WgpuOptions},
+};
+
+/// The camera the engine starts with, looking at the Cornell box.
+pub fn camera(width: u32, height: u32) -> Camera {
+    let camera_position = glam::Vec3::new(0.0, 0.0, 4.0);
+    Camera::new(
+        camera_position,
+        -camera_position.normalize(),
+        width as f32 / height as f32,
+        45.0,
+        0.1,
+        100.0,
+    )
+}
+
+/// Creates a device with the features, or `None` if no adapter supports them.
+pub fn device(features: wgpu::Features) -> Option<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+    let device = pollster::block_on(async {
+        let instance = wgpu::Instance::default();
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await?;
+        if !adapter.features().contains(features) {
+            return None;
+        }
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("Test Device"),
+                    required_features: features,
+                    required_limits: wgpu::Limits::downlevel_defaults()
+                        .using_resolution(adapter.limits()),
+                    memory_hints: wgpu::MemoryHints::Performance,
+                },
+                None,
+            )
+            .await
+            .ok()?;
+
+        Some((adapter, device, queue))
+    });
+    if device.is_none() {
+        no_adapter(&format!("No adapter with {features:?}"));
+    }
+
+    device
+}
+
+/// Creates a headless renderer with the engine's starting scene, or `None` if there's no adapter.
+pub fn headless_renderer(
+    width: u32,
+    height: u32,
+    accumulation: RaytracerAccumulation,
+    settings: &RaytracerSettings,
+) -> Option<Renderer> {
+    let mut renderer = pollster::block_on(Renderer::new_headless(
+        width,
+        height,
+        &camera(width, height),
+        &[1.0, 1.0, 1.0, 1.0],
+        &[DirectionalLight::from_azi_alt(45.0, 45.0)],
+        &[],
+        accumulation,
+        settings,
+        1.0,
+        20.0,
+        GridSpacing::default(),
+        &WgpuOptions::default(),
+    ));
+    match &mut renderer {
+        Some(renderer) => renderer.set_mesh(&Mesh::from_ply(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/cornell-box.ply"
+        ))),
+        None => no_adapter("No adapter to render with"),
+    }
+
+    renderer
+}
+
+// Fails the test unless running without an adapter was allowed
+fn no_adapter(message: &str) {
+    assert!(
+        std::env::var_os("WGPU_TESTS_ALLOW_NO_ADAPTER").is_some(),
+        "{message}, set WGPU_TESTS_ALLOW_NO_ADAPTER to let the GPU tests pass without running"
+    );
+}
+
+/// Draws the mesh with the rasterizer into the renderer's texture and reads it back.
+pub fn render_rasterizer_frame(
+    renderer: &Renderer,
+    is_mesh_culled: bool,
+    is_wireframe_enabled: bool,
+    is_grid_enabled: bool,
+    clear_color: wgpu::Color,
+    render_stats: &mut RenderStats,
+) -> image::RgbaImage {
+    let RenderTarget::Texture(texture) = &renderer.wgpu.target else {
+        panic!("The renderer should be headless");
+    };
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let (index_buffer, num_indices, render_pipeline) = if is_wireframe_enabled {
+        (
+            &renderer.wireframe_index_buffer,
+            renderer.num_wireframe_indices,
+            &renderer.rasterizer.wireframe_render_pipeline,
+        )
+    } else {
+        (
+            &renderer.index_buffer,
+            renderer.num_indices,
+            &renderer.rasterizer.render_pipeline,
+        )
+    };
+
+    let mut encoder =
+        renderer
+            .wgpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Test Command Encoder"),
+            });
+    render_rasterizer(
+        &mut encoder,
+        &view,
+        &renderer.rasterizer.depth_texture,
+        &renderer.vertex_buffer,
+        index_buffer,
+        num_indices,
+        is_mesh_culled,
+        &renderer.rasterizer.bind_group,
+        render_pipeline,
+        is_grid_enabled.then_some(&renderer.grid),
+        clear_color,
+        None,
+        render_stats,
+    );
+    let capture = FrameCapture::new(&renderer.wgpu.device, &mut encoder, texture);
+    renderer.wgpu.queue.submit(Some(encoder.finish()));
+
+    capture.read(&renderer.wgpu.device).unwrap()
+}
+
+/// Copies the buffer into a mappable one and returns its contents.
+pub fn read_buffer(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Vec<u8> {
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Test Staging Buffer"),
+        size: buffer.size(),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Test Command Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, buffer.size());
+    queue.submit(Some(encoder.finish()));
+
+    staging_buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, |result| result.unwrap());
+    device.poll(wgpu::Maintain::Wait);
+
+    let contents = staging_buffer.slice(..).get_mapped_range().to_vec();
+    contents
+}
@@ -11,3 +11,58 @@ pub fn load_icon(path: &std::path::Path) -> winit::window::Icon {
 
     winit::window::Icon::from_rgba(icon_rgba, icon_width, icon_height).expect("Failed to open icon")
 }
+
+/// Returns how long to wait before starting the next frame,
+/// so frames start at least `1 / target_fps` seconds apart.
+/// A `target_fps` that isn't positive, or is NaN, doesn't limit the frames.
+pub fn frame_pacing_delay(
+    target_fps: f32,
+    time_since_last_frame: std::time::Duration,
+) -> std::time::Duration {
+    if target_fps.is_nan() || target_fps <= 0.0 {
+        return std::time::Duration::ZERO;
+    }
+
+    std::time::Duration::from_secs_f32(1.0 / target_fps).saturating_sub(time_since_last_frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn frame_pacing_waits_out_the_rest_of_the_frame() {
+        assert_eq!(
+            frame_pacing_delay(50.0, Duration::from_millis(5)),
+            Duration::from_millis(15)
+        );
+        assert_eq!(
+            frame_pacing_delay(50.0, Duration::ZERO),
+            Duration::from_millis(20)
+        );
+    }
+
+    #[test]
+    fn late_frames_start_straight_away() {
+        assert_eq!(
+            frame_pacing_delay(50.0, Duration::from_millis(20)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            frame_pacing_delay(50.0, Duration::from_millis(35)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn invalid_target_fps_doesnt_wait() {
+        for target_fps in [0.0, -30.0, f32::NAN] {
+            assert_eq!(
+                frame_pacing_delay(target_fps, Duration::ZERO),
+                Duration::ZERO
+            );
+        }
+    }
+}
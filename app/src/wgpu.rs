@@ -66,6 +66,44 @@ impl Resolution {
     }
 }
 
+/// Picks the format the surface is configured with. Colors are linear everywhere in the renderer,
+/// so an sRGB format is preferred to have the surface encode them on write. When the surface only
+/// has linear formats, as on some web backends, the shaders encode their output themselves.
+pub fn preferred_surface_format(formats: &[wgpu::TextureFormat]) -> wgpu::TextureFormat {
+    formats
+        .iter()
+        .copied()
+        .find(|format| format.is_srgb())
+        .unwrap_or(formats[0])
+}
+
+/// Prepends the color functions shared by the shaders that write to a surface of the format,
+/// so their `encode_output_color` only encodes to sRGB when the surface won't do it on write.
+pub fn with_color_encoding(shader_source: &str, surface_format: wgpu::TextureFormat) -> String {
+    let color_shader_source = include_str!("shaders/color.wgsl").replace(
+        "const IS_SRGB_ENCODING_ENABLED: bool = false;",
+        &format!(
+            "const IS_SRGB_ENCODING_ENABLED: bool = {};",
+            !surface_format.is_srgb()
+        ),
+    );
+
+    format!("{color_shader_source}\n{shader_source}")
+}
+
+/// Returns the requested present mode if the surface supports it, otherwise `Fifo`,
+/// which every surface supports. The `Auto` modes always resolve to a supported mode.
+pub fn supported_present_mode(
+    requested: wgpu::PresentMode,
+    supported: &[wgpu::PresentMode],
+) -> wgpu::PresentMode {
+    match requested {
+        wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync => requested,
+        _ if supported.contains(&requested) => requested,
+        _ => wgpu::PresentMode::Fifo,
+    }
+}
+
 pub fn update_buffer<T: NoUninit>(queue: &wgpu::Queue, wgpu_buffer: &wgpu::Buffer, value: &[T]) {
     // TODO: Maybe use encase?
     queue.write_buffer(wgpu_buffer, 0, bytemuck::cast_slice(value));
@@ -79,6 +117,7 @@ pub struct Texture {
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub const ENVIRONMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
@@ -122,13 +161,281 @@ impl Texture {
             sampler,
         }
     }
+
+    pub fn create_environment_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        environment_map: &crate::environment::EnvironmentMap,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: environment_map.width,
+            height: environment_map.height,
+            depth_or_array_layers: 1,
+        };
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::ENVIRONMENT_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        };
+        let texture = device.create_texture(&desc);
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&environment_map.texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(environment_map.width * 4 * size_of::<f32>() as u32),
+                rows_per_image: Some(environment_map.height),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Rgba32Float isn't filterable without an optional feature, so use the nearest texel
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+}
+
+/// How the renderer picks its adapter and device.
+#[derive(Clone, Debug)]
+pub struct WgpuOptions {
+    pub power_preference: wgpu::PowerPreference,
+    // Only use a software adapter
+    pub force_fallback: bool,
+    // Requested on top of the optional features the renderer uses when they're available,
+    // the device can't be created if the adapter doesn't support them
+    pub required_features: wgpu::Features,
+    pub backends: wgpu::Backends,
+}
+
+impl Default for WgpuOptions {
+    fn default() -> Self {
+        Self {
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback: false,
+            required_features: wgpu::Features::empty(),
+            backends: wgpu::Backends::all(),
+        }
+    }
+}
+
+impl WgpuOptions {
+    /// The default options, with the power preference and backends overridden
+    /// by the `WGPU_POWER_PREF` and `WGPU_BACKEND` environment variables when they're set.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+
+        Self {
+            power_preference: wgpu::util::power_preference_from_env()
+                .unwrap_or(default.power_preference),
+            backends: wgpu::util::backend_bits_from_env().unwrap_or(default.backends),
+            ..default
+        }
+    }
+
+    /// The adapter request these options make, for an adapter that can present to `compatible_surface` if there's one.
+    pub fn request_adapter_options<'a, 'b>(
+        &self,
+        compatible_surface: Option<&'a wgpu::Surface<'b>>,
+    ) -> wgpu::RequestAdapterOptions<'a, 'b> {
+        wgpu::RequestAdapterOptions {
+            power_preference: self.power_preference,
+            force_fallback_adapter: self.force_fallback,
+            compatible_surface,
+        }
+    }
+}
+
+/// What the renderer draws its frames into.
+pub enum RenderTarget {
+    Surface(wgpu::Surface<'static>),
+    // Stands in for the surface when rendering without a window, frames stay in it to be read back
+    Texture(wgpu::Texture),
+}
+
+impl RenderTarget {
+    pub const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    pub fn create_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        Self::Texture(device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Render Target Texture"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: config.usage,
+            view_formats: &config.view_formats,
+        }))
+    }
 }
 
 pub struct RendererWgpuResources {
     pub instance: wgpu::Instance,
-    pub surface: wgpu::Surface<'static>,
+    pub target: RenderTarget,
     pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
+    // Also describes the texture when rendering headless
     pub surface_config: wgpu::SurfaceConfiguration,
 }
+
+impl RendererWgpuResources {
+    /// Returns `None` when rendering headless.
+    pub fn surface(&self) -> Option<&wgpu::Surface<'static>> {
+        match &self.target {
+            RenderTarget::Surface(surface) => Some(surface),
+            RenderTarget::Texture(_) => None,
+        }
+    }
+
+    /// Applies `surface_config` to the render target, recreating the texture when rendering headless.
+    pub fn configure_target(&mut self) {
+        match &self.target {
+            RenderTarget::Surface(surface) => surface.configure(&self.device, &self.surface_config),
+            RenderTarget::Texture(_) => {
+                self.target = RenderTarget::create_texture(&self.device, &self.surface_config)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn unsupported_present_modes_fall_back_to_fifo() {
+        let supported = [wgpu::PresentMode::Fifo, wgpu::PresentMode::Mailbox];
+
+        assert_eq!(
+            supported_present_mode(wgpu::PresentMode::Mailbox, &supported),
+            wgpu::PresentMode::Mailbox
+        );
+        assert_eq!(
+            supported_present_mode(wgpu::PresentMode::Immediate, &supported),
+            wgpu::PresentMode::Fifo
+        );
+        // Even when the surface reports no modes
+        assert_eq!(
+            supported_present_mode(wgpu::PresentMode::FifoRelaxed, &[]),
+            wgpu::PresentMode::Fifo
+        );
+        for auto in [wgpu::PresentMode::AutoVsync, wgpu::PresentMode::AutoNoVsync] {
+            assert_eq!(supported_present_mode(auto, &[]), auto);
+        }
+    }
+
+    #[test]
+    fn adapter_is_requested_with_the_options() {
+        let options = WgpuOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback: true,
+            ..Default::default()
+        };
+        let request = options.request_adapter_options(None);
+
+        assert_eq!(
+            request.power_preference,
+            wgpu::PowerPreference::HighPerformance
+        );
+        assert!(request.force_fallback_adapter);
+        assert!(request.compatible_surface.is_none());
+
+        let request = WgpuOptions::default().request_adapter_options(None);
+        assert_eq!(request.power_preference, wgpu::PowerPreference::default());
+        assert!(!request.force_fallback_adapter);
+    }
+
+    #[test]
+    fn srgb_surface_formats_are_preferred() {
+        assert_eq!(
+            preferred_surface_format(&[
+                wgpu::TextureFormat::Bgra8Unorm,
+                wgpu::TextureFormat::Rgba16Float,
+                wgpu::TextureFormat::Bgra8UnormSrgb,
+            ]),
+            wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        // Like WebGPU, which only offers linear surface formats
+        assert_eq!(
+            preferred_surface_format(&[
+                wgpu::TextureFormat::Bgra8Unorm,
+                wgpu::TextureFormat::Rgba16Float,
+            ]),
+            wgpu::TextureFormat::Bgra8Unorm
+        );
+    }
+
+    #[test]
+    fn shaders_only_encode_for_linear_surfaces() {
+        let shader_source = "@fragment\nfn fs_main() {}";
+
+        let srgb_source = with_color_encoding(shader_source, wgpu::TextureFormat::Rgba8UnormSrgb);
+        assert!(srgb_source.contains("const IS_SRGB_ENCODING_ENABLED: bool = false;"));
+        assert!(srgb_source.ends_with(shader_source));
+
+        let linear_source = with_color_encoding(shader_source, wgpu::TextureFormat::Bgra8Unorm);
+        assert!(linear_source.contains("const IS_SRGB_ENCODING_ENABLED: bool = true;"));
+    }
+
+    #[test]
+    fn surface_shaders_compile_with_the_color_functions() {
+        let Some((_, device, _)) = test_utils::device(wgpu::Features::empty()) else {
+            return;
+        };
+
+        for surface_format in [
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            wgpu::TextureFormat::Rgba8Unorm,
+        ] {
+            for shader_source in [
+                include_str!("shaders/rasterizer/main.wgsl"),
+                include_str!("shaders/rasterizer/grid.wgsl"),
+                include_str!("shaders/raytracer/render.wgsl"),
+            ] {
+                device.push_error_scope(wgpu::ErrorFilter::Validation);
+                device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("Test Shader"),
+                    source: wgpu::ShaderSource::Wgsl(
+                        with_color_encoding(shader_source, surface_format).into(),
+                    ),
+                });
+                let error = pollster::block_on(device.pop_error_scope());
+
+                assert!(error.is_none(), "{surface_format:?}: {error:?}");
+            }
+        }
+    }
+}